@@ -0,0 +1,9 @@
+#ifndef __STDARG_H
+#define __STDARG_H
+
+typedef __builtin_va_list va_list;
+
+// glibc declares the v*printf family with this name
+typedef va_list __gnuc_va_list;
+
+#endif
@@ -1,36 +1,62 @@
-use crate::lvar::LVar;
-use crate::sema::{add_type, new_type_array, Type, TypeKind};
-use crate::sema::{new_type_int, new_type_ptr};
-use crate::util::{error, find_lvar};
+use crate::lvar::{LVar, Scope};
+use crate::sema::{add_type, new_type_int, Member, Type, TypeKind};
+use crate::util::{align_to, error, error_at, find_lvar};
 
 #[derive(Clone, Debug)]
 pub enum NodeKind {
-    NdAdd,     // +
-    NdSub,     // -
-    NdMul,     // *
-    NdDiv,     // /
-    NdNeg,     // unary -
-    NdEq,      // ==
-    NdNe,      // !=
-    NdGt,      // >
-    NdGe,      // >=
-    NdLt,      // <
-    NdLe,      // <=
-    NdAssign,  // =
-    NdDeref,   // *
-    NdAddr,    // &
-    NdNum,     // Integer
-    NdLvar,    // Local variable
-    NdGvar,    // Global variable
-    NdReturn,  // Return
-    NdIf,      // If
-    NdElse,    // Else
-    NdWhile,   // While
-    NdFor,     // For
-    NdBlock,   // Block
-    NdFunc,    // Function
-    NdVardef,  // Variable definition
-    NdGVardef, // Global variable definition
+    NdAdd,      // +
+    NdSub,      // -
+    NdMul,      // *
+    NdDiv,      // /
+    NdMod,      // %
+    NdNeg,      // unary -
+    NdEq,       // ==
+    NdNe,       // !=
+    NdGt,       // >
+    NdGe,       // >=
+    NdLt,       // <
+    NdLe,       // <=
+    NdLogAnd,   // &&
+    NdLogOr,    // ||
+    NdNot,      // !
+    NdBitAnd,   // &
+    NdBitOr,    // |
+    NdBitXor,   // ^
+    NdBitNot,   // ~
+    NdShl,      // <<
+    NdShr,      // >>
+    NdAssign,   // =
+    NdAssignOp, // compound assignment such as +=
+    NdPostOp,   // postfix ++ or --
+    NdComma,    // ,
+    NdDeref,    // *
+    NdAddr,     // &
+    NdCast,     // (type) expr
+    NdMember,   // . struct member
+    NdNum,      // Integer
+    NdLvar,     // Local variable
+    NdGvar,     // Global variable
+    NdReturn,   // Return
+    NdIf,       // If
+    NdElse,     // Else
+    NdCond,     // ?:
+    NdWhile,    // While
+    NdDoWhile,  // Do-while
+    NdFor,      // For
+    NdSwitch,   // Switch
+    NdCase,     // Case
+    NdDefault,  // Default
+    NdBreak,    // Break
+    NdContinue, // Continue
+    NdLabel,    // Labeled statement
+    NdGoto,     // Goto
+    NdGotoExpr, // Computed goto
+    NdLabelVal, // Address of a label
+    NdBlock,    // Block
+    NdFunc,     // Function
+    NdVardef,   // Variable definition
+    NdGVardef,  // Global variable definition
+    NdMemzero,  // Zero-fill a local variable
 }
 
 #[derive(Clone, Debug)]
@@ -39,8 +65,9 @@ pub struct Node {
     pub lhs: Option<Box<Node>>,
     pub rhs: Option<Box<Node>>,
     pub name: String,
-    pub val: i32,
+    pub val: i64,
     pub offset: i32,
+    pub label: usize, // id of the jump labels of loops, switches, cases, goto and their targets
     pub var_type: Option<Box<Type>>,
     pub stmts: Vec<Node>,
 }
@@ -53,6 +80,7 @@ pub fn new_node(kind: NodeKind, lhs: Option<Box<Node>>, rhs: Option<Box<Node>>)
         name: String::new(),
         val: 0,
         offset: 0,
+        label: 0,
         var_type: None,
         stmts: Vec::new(),
     };
@@ -60,7 +88,26 @@ pub fn new_node(kind: NodeKind, lhs: Option<Box<Node>>, rhs: Option<Box<Node>>)
     node
 }
 
-pub fn new_node_num(val: i32) -> Node {
+// `lhs op= rhs`; the rhs holds `lhs op rhs` so that sema types the operation,
+// but codegen evaluates the address of lhs only once
+pub fn new_node_assign_op(kind: NodeKind, lhs: Node, rhs: Node) -> Node {
+    let op = new_node(kind, Some(Box::new(lhs.clone())), Some(Box::new(rhs)));
+    new_node(
+        NodeKind::NdAssignOp,
+        Some(Box::new(lhs)),
+        Some(Box::new(op)),
+    )
+}
+
+// `lhs++` or `lhs--`, laid out like `lhs += 1` or `lhs -= 1` but with the
+// old value of lhs as its value
+pub fn new_node_post_op(kind: NodeKind, lhs: Node) -> Node {
+    let mut node = new_node_assign_op(kind, lhs, new_node_num(1));
+    node.kind = NodeKind::NdPostOp;
+    node
+}
+
+pub fn new_node_num(val: i64) -> Node {
     Node {
         kind: NodeKind::NdNum,
         lhs: None,
@@ -68,13 +115,72 @@ pub fn new_node_num(val: i32) -> Node {
         name: String::new(),
         val,
         offset: 0,
+        label: 0,
         var_type: new_type_int(),
         stmts: Vec::new(),
     }
 }
 
-pub fn new_node_func(name: String, args: Vec<Node>) -> Node {
-    let func_type = new_type_int();
+pub fn new_node_cast(expr: Node, ty: Option<Box<Type>>) -> Node {
+    Node {
+        kind: NodeKind::NdCast,
+        lhs: Some(Box::new(expr)),
+        rhs: None,
+        name: String::new(),
+        val: 0,
+        offset: 0,
+        label: 0,
+        var_type: ty,
+        stmts: Vec::new(),
+    }
+}
+
+// the member of the struct or union lhs; its offset is kept in the node, and
+// it has the qualifiers of lhs as well as its own, which for an array are
+// those of its elements
+pub fn new_node_member(lhs: Node, member: &Member) -> Node {
+    let mut ty = member.ty.clone();
+    let struct_ty = lhs.var_type.as_ref().unwrap();
+    let mut elem = &mut ty;
+    loop {
+        elem.is_const |= struct_ty.is_const;
+        elem.is_volatile |= struct_ty.is_volatile;
+        if elem.ty != TypeKind::TyArray {
+            break;
+        }
+        elem = elem.ptr_to.as_mut().unwrap();
+    }
+    Node {
+        kind: NodeKind::NdMember,
+        lhs: Some(Box::new(lhs)),
+        rhs: None,
+        name: member.name.clone(),
+        val: 0,
+        offset: member.offset as i32,
+        label: 0,
+        var_type: Some(Box::new(ty)),
+        stmts: Vec::new(),
+    }
+}
+
+// a call returns what the function was declared to return, and a function
+// that was not declared returns int
+pub fn new_node_func(
+    mut name: String,
+    args: Vec<Node>,
+    lvar: &Scope,
+    gvar: &Option<Box<LVar>>,
+) -> Node {
+    let mut func_type = new_type_int();
+    if lvar.find(&name).is_none() {
+        if let Some(func) = find_lvar(gvar, &name).filter(|func| func.ty.ty == TypeKind::TyFunc) {
+            func_type = func.ty.ptr_to.clone();
+            // a declaration may give the function another symbol name
+            if let Some(label) = func.global {
+                name = label;
+            }
+        }
+    }
     Node {
         kind: NodeKind::NdFunc,
         lhs: None,
@@ -82,34 +188,34 @@ pub fn new_node_func(name: String, args: Vec<Node>) -> Node {
         name,
         val: 0,
         offset: 0,
+        label: 0,
         var_type: func_type,
         stmts: args,
     }
 }
 
+// a use of the variable called name, which appears at loc
 pub fn new_node_lvar(
     name: String,
-    lvar: &mut Option<Box<LVar>>,
+    loc: usize,
+    lvar: &mut Scope,
     gvar: &mut Option<Box<LVar>>,
 ) -> Node {
-    let lvar = if let Some(lvar) = find_lvar(lvar, &name) {
+    let lvar = if let Some(lvar) = lvar.find(&name) {
+        if lvar.is_typedef {
+            error_at(loc, &format!("unexpected type name '{}'", name));
+        }
+        if let Some(label) = lvar.global {
+            // a static local or an extern declared inside the function
+            return new_node_gvar(label, lvar.ty);
+        }
         *lvar
     } else {
         // global variable
         if let Some(gvar) = find_lvar(gvar, &name) {
-            return Node {
-                kind: NodeKind::NdGvar,
-                lhs: None,
-                rhs: None,
-                name,
-                val: 0,
-                offset: gvar.offset,
-                var_type: Some(Box::new(gvar.ty.clone())),
-                stmts: Vec::new(),
-            };
+            return new_node_gvar(name, gvar.ty);
         } else {
-            println!("{}", name);
-            error("not declared variable");
+            error_at(loc, &format!("undeclared variable '{}'", name));
         }
     };
 
@@ -122,55 +228,34 @@ pub fn new_node_lvar(
         name,
         val: 0,
         offset: lvar.offset,
+        label: 0,
         var_type: Some(Box::new(node_type)),
         stmts: Vec::new(),
     }
 }
 
-pub fn new_node_gvar_def(name: String, depth_pointer: usize, gvar: &mut Option<Box<LVar>>) -> Node {
-    let mut node_type = new_type_int();
-    for _ in 0..depth_pointer {
-        node_type = new_type_ptr(node_type);
-    }
-
-    let offset = if let Some(_) = find_lvar(gvar, &name) {
-        error("variable already declared");
-    } else {
-        node_type.clone().unwrap().size as i32
-    };
-
-    *gvar = Some(Box::new(LVar::new(
-        gvar.take(),
-        name.clone(),
-        offset,
-        node_type.clone().unwrap().as_ref().clone(),
-    )));
-
+pub fn new_node_gvar(name: String, ty: Type) -> Node {
     Node {
-        kind: NodeKind::NdGVardef,
+        kind: NodeKind::NdGvar,
         lhs: None,
         rhs: None,
         name,
         val: 0,
-        offset,
-        var_type: node_type,
+        offset: ty.size() as i32,
+        label: 0,
+        var_type: Some(Box::new(ty)),
         stmts: Vec::new(),
     }
 }
 
-pub fn new_node_gvar_def_array(
+pub fn new_node_gvar_def(
     name: String,
-    size: i32,
+    node_type: Option<Box<Type>>,
     gvar: &mut Option<Box<LVar>>,
-    ty: TypeKind,
 ) -> Node {
-    let offset = if let Some(_) = find_lvar(gvar, &name) {
-        error("variable already declared");
-    } else {
-        size * ty.size()
-    };
-
-    let node_type = new_type_array(new_type_int(), size as usize);
+    // a global may be declared many times, so the parser checks that it is
+    // defined only once
+    let offset = node_type.clone().unwrap().size() as i32;
 
     *gvar = Some(Box::new(LVar::new(
         gvar.take(),
@@ -186,33 +271,30 @@ pub fn new_node_gvar_def_array(
         name,
         val: 0,
         offset,
+        label: 0,
         var_type: node_type,
         stmts: Vec::new(),
     }
 }
 
-pub fn new_node_var_def(name: String, depth_pointer: usize, lvar: &mut Option<Box<LVar>>) -> Node {
-    let mut node_type = new_type_int();
-    for _ in 0..depth_pointer {
-        node_type = new_type_ptr(node_type);
-    }
+// place a new local below the previously declared ones, aligned for its type
+fn local_offset(lvar: &mut Scope, ty: &Type) -> i32 {
+    lvar.stack_size = align_to(lvar.stack_size + ty.size() as i32, ty.align() as i32);
+    lvar.stack_size
+}
 
-    let offset = if let Some(_) = find_lvar(lvar, &name) {
+pub fn new_node_var_def(name: String, node_type: Option<Box<Type>>, lvar: &mut Scope) -> Node {
+    let offset = if let Some(_) = lvar.find_in_current(&name) {
         error("variable already declared");
     } else {
-        if let Some(lvar) = lvar {
-            lvar.offset + node_type.clone().unwrap().size as i32
-        } else {
-            8 + node_type.clone().unwrap().size as i32
-        }
+        local_offset(lvar, node_type.as_ref().unwrap())
     };
 
-    *lvar = Some(Box::new(LVar::new(
-        lvar.take(),
+    lvar.push(
         name.clone(),
         offset,
         node_type.clone().unwrap().as_ref().clone(),
-    )));
+    );
 
     Node {
         kind: NodeKind::NdVardef,
@@ -221,44 +303,23 @@ pub fn new_node_var_def(name: String, depth_pointer: usize, lvar: &mut Option<Bo
         name,
         val: 0,
         offset,
+        label: 0,
         var_type: node_type,
         stmts: Vec::new(),
     }
 }
 
-pub fn new_node_var_def_array(
-    name: String,
-    size: i32,
-    lvar: &mut Option<Box<LVar>>,
-    ty: TypeKind,
-) -> Node {
-    let offset = if let Some(_) = find_lvar(lvar, &name) {
-        error("variable already declared");
-    } else {
-        if let Some(lvar) = lvar {
-            lvar.offset + (size * ty.size())
-        } else {
-            8 + size * ty.size()
-        }
-    };
-
-    let node_type = new_type_array(new_type_int(), size as usize);
-
-    *lvar = Some(Box::new(LVar::new(
-        lvar.take(),
-        name.clone(),
-        offset,
-        node_type.clone().unwrap().as_ref().clone(),
-    )));
-
+// zero-fill the local variable def before its initializer is assigned
+pub fn new_node_memzero(def: &Node) -> Node {
     Node {
-        kind: NodeKind::NdVardef,
+        kind: NodeKind::NdMemzero,
         lhs: None,
         rhs: None,
-        name,
+        name: def.name.clone(),
         val: 0,
-        offset,
-        var_type: node_type,
+        offset: def.offset,
+        label: 0,
+        var_type: def.var_type.clone(),
         stmts: Vec::new(),
     }
 }
@@ -271,6 +332,7 @@ pub fn new_node_block(stmts: Vec<Node>) -> Node {
         name: String::new(),
         val: 0,
         offset: 0,
+        label: 0,
         var_type: None,
         stmts,
     }
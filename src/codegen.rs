@@ -1,16 +1,24 @@
 use crate::ast::{Node, NodeKind};
-use crate::sema::TypeKind;
+use crate::sema::{cmp_type, is_integer, Type, TypeKind};
 use crate::util;
 
 fn load(node: Node) {
     println!("  pop rax");
-    if let TypeKind::TyArray = node.clone().var_type.as_ref().unwrap().ty {
-    } else {
-        if node.var_type.unwrap().ty == TypeKind::TyInt {
-            println!("  mov eax, [rax]");
-        } else {
-            println!("  mov rax, [rax]");
-        }
+    let ty = node.var_type.unwrap();
+    match ty.ty {
+        // an array, struct or function stands for its address
+        TypeKind::TyArray | TypeKind::TyStruct | TypeKind::TyUnion | TypeKind::TyFunc => {}
+        _ => match (ty.size(), ty.is_unsigned) {
+            (1, false) => println!("  movsx rax, BYTE PTR [rax]"),
+            (1, true) => println!("  movzx rax, BYTE PTR [rax]"),
+            (2, false) => println!("  movsx rax, WORD PTR [rax]"),
+            (2, true) => println!("  movzx rax, WORD PTR [rax]"),
+            // sign-extend so that 64-bit arithmetic such as sar sees negative ints
+            (4, false) => println!("  movsxd rax, DWORD PTR [rax]"),
+            // writing eax clears the upper half of rax
+            (4, true) => println!("  mov eax, DWORD PTR [rax]"),
+            _ => println!("  mov rax, [rax]"),
+        },
     }
     println!("  push rax");
 }
@@ -18,14 +26,54 @@ fn load(node: Node) {
 fn store(node: Node) {
     println!("  pop rdi");
     println!("  pop rax");
-    if node.lhs.unwrap().var_type.unwrap().ty == TypeKind::TyInt {
-        println!("  mov [rax], edi");
-    } else {
-        println!("  mov [rax], rdi");
+    let ty = node.lhs.unwrap().var_type.unwrap();
+    match ty.ty {
+        // rdi holds the address of the struct to copy from
+        TypeKind::TyStruct | TypeKind::TyUnion => {
+            for i in 0..ty.size() {
+                println!("  mov r8b, [rdi+{}]", i);
+                println!("  mov [rax+{}], r8b", i);
+            }
+        }
+        TypeKind::TyBool => {
+            println!("  cmp rdi, 0");
+            println!("  setne dil");
+            println!("  movzx edi, dil");
+            println!("  mov [rax], dil");
+        }
+        _ => match ty.size() {
+            1 => println!("  mov [rax], dil"),
+            2 => println!("  mov [rax], di"),
+            4 => println!("  mov [rax], edi"),
+            _ => println!("  mov [rax], rdi"),
+        },
     }
     println!("  push rdi");
 }
 
+// convert the value in rax to ty, whose integer types narrower than 64 bits
+// are kept sign- or zero-extended
+fn cast(ty: &Type) {
+    if ty.ty == TypeKind::TyBool {
+        println!("  cmp rax, 0");
+        println!("  setne al");
+        println!("  movzx eax, al");
+        return;
+    }
+    if !is_integer(ty) {
+        return;
+    }
+    match (ty.size(), ty.is_unsigned) {
+        (1, false) => println!("  movsx rax, al"),
+        (1, true) => println!("  movzx eax, al"),
+        (2, false) => println!("  movsx rax, ax"),
+        (2, true) => println!("  movzx eax, ax"),
+        (4, false) => println!("  movsxd rax, eax"),
+        (4, true) => println!("  mov eax, eax"),
+        _ => {}
+    }
+}
+
 pub fn gen_lval(node: Node) {
     // check if node is an lvalue
     // address of the variable is pushed to the stack
@@ -51,18 +99,40 @@ pub fn gen_lval(node: Node) {
         println!("  push rax");
         return;
     }
+    if let NodeKind::NdMember = node.kind {
+        gen_lval(*node.lhs.unwrap());
+        println!("  pop rax");
+        println!("  add rax, {}", node.offset);
+        println!("  push rax");
+        return;
+    }
     // if node is a dereference, push the address of the variable to the stack
     if let NodeKind::NdDeref = node.kind {
         gen(*node.rhs.unwrap());
         return;
     }
+    // a conditional of struct or union type already has the address of the
+    // operand it chose as its value
+    if let NodeKind::NdCond = node.kind {
+        let ty = node.var_type.as_ref().unwrap();
+        if matches!(ty.ty, TypeKind::TyStruct | TypeKind::TyUnion) {
+            gen(node);
+            return;
+        }
+    }
     util::error("not an lvalue");
 }
 
 pub fn gen(node: Node) {
     match node.kind {
         NodeKind::NdNum => {
-            println!("  push {}", node.val);
+            // push only takes a sign-extended 32-bit immediate
+            if i32::try_from(node.val).is_ok() {
+                println!("  push {}", node.val);
+            } else {
+                println!("  mov rax, {}", node.val);
+                println!("  push rax");
+            }
             return;
         }
         NodeKind::NdLvar => {
@@ -87,6 +157,14 @@ pub fn gen(node: Node) {
 
             return;
         }
+        NodeKind::NdMemzero => {
+            println!("  mov rdi, rbp");
+            println!("  sub rdi, {}", node.offset);
+            println!("  mov rcx, {}", node.var_type.unwrap().size());
+            println!("  mov al, 0");
+            println!("  rep stosb");
+            return;
+        }
         NodeKind::NdAssign => {
             gen_lval(*node.clone().lhs.unwrap());
             gen(*node.clone().rhs.unwrap());
@@ -94,19 +172,70 @@ pub fn gen(node: Node) {
             store(node.clone());
             return;
         }
+        NodeKind::NdAssignOp => {
+            // keep the address on the stack and load through a copy of it
+            let op = *node.clone().rhs.unwrap();
+            gen_lval(*node.clone().lhs.unwrap());
+            println!("  mov rax, [rsp]");
+            println!("  push rax");
+            load(*node.clone().lhs.unwrap());
+            gen(*op.clone().rhs.unwrap());
+            gen_arith(op);
+            // the value of the assignment is what the lhs holds afterwards
+            cast(node.var_type.as_ref().unwrap());
+            println!("  push rax");
+
+            store(node.clone());
+            return;
+        }
+        NodeKind::NdPostOp => {
+            // the old value is kept below the address while the new one is
+            // computed and stored
+            let op = *node.clone().rhs.unwrap();
+            gen_lval(*node.clone().lhs.unwrap());
+            println!("  mov rax, [rsp]");
+            println!("  push rax");
+            load(*node.clone().lhs.unwrap());
+            println!("  pop rax");
+            println!("  pop rdi");
+            println!("  push rax");
+            println!("  push rdi");
+            println!("  push rax");
+            gen(*op.clone().rhs.unwrap());
+            gen_arith(op);
+            cast(node.var_type.as_ref().unwrap());
+            println!("  push rax");
+
+            store(node.clone());
+            println!("  pop rax");
+            return;
+        }
         NodeKind::NdDeref => {
             gen(*node.clone().rhs.unwrap());
 
             load(node);
             return;
         }
+        NodeKind::NdMember => {
+            gen_lval(node.clone());
+
+            load(node);
+            return;
+        }
+        NodeKind::NdCast => {
+            gen(*node.clone().lhs.unwrap());
+            println!("  pop rax");
+            cast(node.var_type.as_ref().unwrap());
+        }
         NodeKind::NdAddr => {
             gen_lval(*node.clone().rhs.unwrap());
             return;
         }
         NodeKind::NdReturn => {
-            gen(*node.clone().lhs.unwrap());
-            println!("  pop rax");
+            if let Some(val) = node.lhs {
+                gen(*val);
+                println!("  pop rax");
+            }
             println!("  mov rsp, rbp");
             println!("  pop rbp");
             println!("  ret");
@@ -131,20 +260,55 @@ pub fn gen(node: Node) {
             }
             return;
         }
-        NodeKind::NdWhile => {
+        NodeKind::NdComma => {
+            gen(*node.clone().lhs.unwrap());
+            println!("  pop rax");
+            gen(*node.clone().rhs.unwrap());
+            return;
+        }
+        NodeKind::NdCond => {
+            // each branch pushes its own value
             let label = util::gen_label();
+            let branches = *node.clone().rhs.unwrap();
+            gen(*node.clone().lhs.unwrap());
+            println!("  pop rax");
+            println!("  cmp rax, 0");
+            println!("  je .Lelse{}", label);
+            gen(*branches.lhs.unwrap());
+            println!("  jmp .Lend{}", label);
+            println!(".Lelse{}:", label);
+            gen(*branches.rhs.unwrap());
+            println!(".Lend{}:", label);
+            return;
+        }
+        NodeKind::NdWhile => {
+            let label = node.label;
             println!(".Lbegin{}:", label);
             gen(*node.clone().lhs.unwrap());
             println!("  pop rax");
             println!("  cmp rax, 0");
             println!("  je .Lend{}", label);
             gen(*node.clone().rhs.unwrap());
+            println!(".Lcontinue{}:", label);
             println!("  jmp .Lbegin{}", label);
             println!(".Lend{}:", label);
             return;
         }
+        NodeKind::NdDoWhile => {
+            // continue re-evaluates the condition rather than restarting the body
+            let label = node.label;
+            println!(".Lbegin{}:", label);
+            gen(*node.clone().lhs.unwrap());
+            println!(".Lcontinue{}:", label);
+            gen(*node.clone().rhs.unwrap());
+            println!("  pop rax");
+            println!("  cmp rax, 0");
+            println!("  jne .Lbegin{}", label);
+            println!(".Lend{}:", label);
+            return;
+        }
         NodeKind::NdFor => {
-            let label = util::gen_label();
+            let label = node.label;
             if let Some(init) = node.clone().lhs {
                 gen(*init);
             }
@@ -156,6 +320,7 @@ pub fn gen(node: Node) {
                 println!("  je .Lend{}", label);
             }
             gen(*node.clone().rhs.unwrap().rhs.unwrap().rhs.unwrap());
+            println!(".Lcontinue{}:", label);
             if let Some(inc) = node.clone().rhs.unwrap().rhs.unwrap().lhs {
                 gen(*inc);
             }
@@ -163,6 +328,45 @@ pub fn gen(node: Node) {
             println!(".Lend{}:", label);
             return;
         }
+        NodeKind::NdSwitch => {
+            gen(*node.clone().lhs.unwrap());
+            println!("  pop rax");
+            gen_switch_dispatch(&node);
+            gen(*node.clone().rhs.unwrap());
+            println!(".Lend{}:", node.label);
+            return;
+        }
+        NodeKind::NdCase | NodeKind::NdDefault => {
+            println!(".Lcase{}:", node.label);
+            gen(*node.clone().lhs.unwrap());
+            return;
+        }
+        NodeKind::NdLabel => {
+            println!(".Llabel{}:", node.label);
+            gen(*node.clone().lhs.unwrap());
+            return;
+        }
+        NodeKind::NdGoto => {
+            println!("  jmp .Llabel{}", node.label);
+            return;
+        }
+        NodeKind::NdGotoExpr => {
+            gen(*node.clone().lhs.unwrap());
+            println!("  pop rax");
+            println!("  jmp rax");
+            return;
+        }
+        NodeKind::NdLabelVal => {
+            println!("  lea rax, .Llabel{}[rip]", node.label);
+        }
+        NodeKind::NdBreak => {
+            println!("  jmp .Lend{}", node.label);
+            return;
+        }
+        NodeKind::NdContinue => {
+            println!("  jmp .Lcontinue{}", node.label);
+            return;
+        }
         NodeKind::NdBlock => {
             for stmt in node.stmts {
                 gen(stmt.clone());
@@ -173,52 +377,104 @@ pub fn gen(node: Node) {
             return;
         }
         NodeKind::NdFunc => {
-            let regs = vec!["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
-            for arg in node.clone().stmts {
-                gen(arg);
+            let regs = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+            let args = node.stmts.clone();
+            // arguments after the sixth are passed on the stack, the seventh
+            // at rsp, which the ABI wants 16-byte aligned at the call; the
+            // values pushed so far may have left rsp anywhere, so align it
+            // first and keep the old value to restore
+            let stack_args = args.len().saturating_sub(regs.len());
+            let padding = if stack_args.is_multiple_of(2) { 8 } else { 0 };
+            println!("  mov rax, rsp");
+            println!("  and rsp, -16");
+            println!("  push rax");
+            println!("  sub rsp, {}", padding);
+            // the last argument is pushed first, so that the first is on top
+            for arg in args.iter().rev() {
+                gen(arg.clone());
             }
-            let mut i = node.clone().stmts.len();
-            for _ in node.clone().stmts {
-                i -= 1;
-                println!("  pop rax");
-                println!("  mov {}, rax", regs[i]);
+            if let Some(callee) = node.lhs.clone() {
+                gen(*callee);
+                println!("  pop r10");
+            }
+            for reg in regs.iter().take(args.len()) {
+                println!("  pop {}", reg);
+            }
+            // a variadic callee reads the number of vector registers used from al
+            println!("  mov eax, 0");
+            if node.lhs.is_some() {
+                println!("  call r10");
+            } else {
+                println!("  call {}", node.name);
             }
-            println!("  call {}", node.clone().name);
+            println!("  add rsp, {}", 8 * stack_args + padding);
+            println!("  pop rsp");
+            // the callee only sets as many bits of rax as its return type has
+            cast(node.var_type.as_ref().unwrap());
             println!("  push rax");
             return;
         }
-        NodeKind::NdAdd => {
-            let lty = node.clone().lhs.unwrap().var_type.unwrap();
-            let rty = node.clone().rhs.unwrap().var_type.unwrap();
-            match (lty.ty, rty.ty) {
-                (TypeKind::TyInt, TypeKind::TyInt) => {
-                    gen_binary_op(node.clone(), "add");
-                }
-                _ => {
-                    gen_ptr_binary_op(node.clone(), "add");
-                }
-            }
+        NodeKind::NdLogAnd => {
+            // the right-hand side is evaluated only if the left is true
+            let label = util::gen_label();
+            gen(*node.clone().lhs.unwrap());
+            println!("  pop rax");
+            println!("  cmp rax, 0");
+            println!("  je .Lfalse{}", label);
+            gen(*node.clone().rhs.unwrap());
+            println!("  pop rax");
+            println!("  cmp rax, 0");
+            println!("  je .Lfalse{}", label);
+            println!("  mov rax, 1");
+            println!("  jmp .Lend{}", label);
+            println!(".Lfalse{}:", label);
+            println!("  mov rax, 0");
+            println!(".Lend{}:", label);
         }
-        NodeKind::NdSub => {
-            let lty = node.clone().lhs.unwrap().var_type.unwrap();
-            let rty = node.clone().rhs.unwrap().var_type.unwrap();
-            match (lty.ty, rty.ty) {
-                (TypeKind::TyInt, TypeKind::TyInt) => {
-                    gen_binary_op(node.clone(), "sub");
-                }
-                _ => {
-                    gen_ptr_binary_op(node.clone(), "sub");
-                }
-            }
+        NodeKind::NdLogOr => {
+            // the right-hand side is evaluated only if the left is false
+            let label = util::gen_label();
+            gen(*node.clone().lhs.unwrap());
+            println!("  pop rax");
+            println!("  cmp rax, 0");
+            println!("  jne .Ltrue{}", label);
+            gen(*node.clone().rhs.unwrap());
+            println!("  pop rax");
+            println!("  cmp rax, 0");
+            println!("  jne .Ltrue{}", label);
+            println!("  mov rax, 0");
+            println!("  jmp .Lend{}", label);
+            println!(".Ltrue{}:", label);
+            println!("  mov rax, 1");
+            println!(".Lend{}:", label);
         }
-        NodeKind::NdNeg => {
-            gen_binary_op(node.clone(), "sub");
+        NodeKind::NdNot => {
+            gen(*node.clone().rhs.unwrap());
+            println!("  pop rax");
+            println!("  cmp rax, 0");
+            println!("  sete al");
+            println!("  movzb rax, al");
         }
-        NodeKind::NdMul => {
-            gen_binary_op(node.clone(), "imul");
+        NodeKind::NdAdd
+        | NodeKind::NdSub
+        | NodeKind::NdNeg
+        | NodeKind::NdMul
+        | NodeKind::NdDiv
+        | NodeKind::NdMod
+        | NodeKind::NdBitAnd
+        | NodeKind::NdBitOr
+        | NodeKind::NdBitXor
+        | NodeKind::NdShl
+        | NodeKind::NdShr => {
+            gen(*node.clone().lhs.unwrap());
+            gen(*node.clone().rhs.unwrap());
+            gen_arith(node.clone());
         }
-        NodeKind::NdDiv => {
-            gen_binary_op(node.clone(), "idiv");
+        NodeKind::NdBitNot => {
+            gen(*node.clone().rhs.unwrap());
+            println!("  pop rax");
+            println!("  not rax");
+            cast(node.var_type.as_ref().unwrap());
         }
         NodeKind::NdEq => {
             gen_cmp(node.clone(), "eq");
@@ -244,34 +500,137 @@ pub fn gen(node: Node) {
     println!("  push rax");
 }
 
-fn gen_ptr_binary_op(node: Node, op: &str) {
-    if let Some(lhs) = node.lhs.clone() {
-        gen(*lhs);
+// jump from a switch to the case matching the value in rax, either through a
+// jump table when the case values are dense or through a chain of compares;
+// the value is compared in the width of its promoted type
+fn gen_switch_dispatch(node: &Node) {
+    let cases: Vec<&Node> = node
+        .stmts
+        .iter()
+        .filter(|case| matches!(case.kind, NodeKind::NdCase))
+        .collect();
+    let default = match node
+        .stmts
+        .iter()
+        .find(|case| matches!(case.kind, NodeKind::NdDefault))
+    {
+        Some(default) => format!(".Lcase{}", default.label),
+        None => format!(".Lend{}", node.label),
+    };
+    let is_long = node.lhs.as_ref().unwrap().var_type.as_ref().unwrap().size() == 8;
+    let reg = if is_long { "rax" } else { "eax" };
+    // an immediate operand has at most 32 bits, which a 64-bit compare
+    // sign-extends, so a wider value goes through rdi
+    let operand = |val: i64| {
+        if !is_long {
+            (val as i32).to_string()
+        } else if i32::try_from(val).is_ok() {
+            val.to_string()
+        } else {
+            println!("  mov rdi, {}", val);
+            "rdi".to_string()
+        }
+    };
+
+    let min = cases.iter().map(|case| case.val).min().unwrap_or(0);
+    let max = cases.iter().map(|case| case.val).max().unwrap_or(0);
+    // case values may be far enough apart to overflow an i64
+    let range = max as i128 - min as i128 + 1;
+    // a table is worth it when it has a few entries and is at least half full
+    if cases.len() < 4 || range > 2 * cases.len() as i128 {
+        for case in cases {
+            println!("  cmp {}, {}", reg, operand(case.val));
+            println!("  je .Lcase{}", case.label);
+        }
+        println!("  jmp {}", default);
+        return;
     }
-    if let Some(rhs) = node.rhs.clone() {
-        gen(*rhs);
+
+    // the subtraction wraps values below min around to large unsigned ones
+    println!("  sub {}, {}", reg, operand(min));
+    println!("  cmp {}, {}", reg, range - 1);
+    println!("  ja {}", default);
+    // entries are offsets from the table, which need no relocation when
+    // the table is linked into a position-independent executable
+    println!("  lea rdi, .Ltable{}[rip]", node.label);
+    println!("  movsxd rax, DWORD PTR [rdi+rax*4]");
+    println!("  add rax, rdi");
+    println!("  jmp rax");
+    println!(".section .rodata");
+    println!(".align 4");
+    println!(".Ltable{}:", node.label);
+    for val in min..=max {
+        match cases.iter().find(|case| case.val == val) {
+            Some(case) => println!("  .long .Lcase{}-.Ltable{}", case.label, node.label),
+            None => println!("  .long {}-.Ltable{}", default, node.label),
+        }
+    }
+    println!(".section .text");
+}
+
+// pop both operands of node from the stack and leave the result in rax
+fn gen_arith(node: Node) {
+    let ty = node.var_type.clone().unwrap();
+    let op = match node.kind {
+        NodeKind::NdAdd => "add",
+        NodeKind::NdSub | NodeKind::NdNeg => "sub",
+        NodeKind::NdMul => "imul",
+        NodeKind::NdDiv | NodeKind::NdMod if ty.is_unsigned => "div",
+        NodeKind::NdDiv | NodeKind::NdMod => "idiv",
+        NodeKind::NdBitAnd => "and",
+        NodeKind::NdBitOr => "or",
+        NodeKind::NdBitXor => "xor",
+        NodeKind::NdShl => "shl",
+        // the left operand decides whether a right shift is arithmetic
+        NodeKind::NdShr if ty.is_unsigned => "shr",
+        NodeKind::NdShr => "sar",
+        _ => util::error("not an arithmetic operator"),
+    };
+    if let NodeKind::NdAdd | NodeKind::NdSub = node.kind {
+        let lty = node.clone().lhs.unwrap().var_type.unwrap();
+        let rty = node.clone().rhs.unwrap().var_type.unwrap();
+        if !(is_integer(&lty) && is_integer(&rty)) {
+            gen_ptr_binary_op(node, op);
+            return;
+        }
     }
+    gen_binary_op(op, &ty);
+    if let NodeKind::NdMod = node.kind {
+        // the divide leaves the remainder in rdx
+        println!("  mov rax, rdx");
+    }
+    // the 64-bit operation may have carried into bits the type does not have
+    cast(&ty);
+}
+
+fn gen_ptr_binary_op(node: Node, op: &str) {
     println!("  pop rdi");
 
     let ty = node.lhs.unwrap().var_type.unwrap().ptr_to.unwrap();
-    let ty_size = ty.size;
+    let ty_size = ty.size();
     println!("  imul rdi, {}", ty_size);
     println!("  pop rax");
     println!("  {} rax, rdi", op);
 }
 
-fn gen_binary_op(node: Node, op: &str) {
-    if let Some(lhs) = node.lhs.clone() {
-        gen(*lhs);
-    }
-    if let Some(rhs) = node.rhs.clone() {
-        gen(*rhs);
-    }
+// both operands are converted to ty first, which only changes anything for
+// a negative int in an unsigned int division
+fn gen_binary_op(op: &str, ty: &Type) {
     println!("  pop rdi");
     println!("  pop rax");
     if op == "idiv" {
         println!("  cqo");
         println!("  idiv rdi");
+    } else if op == "div" {
+        if ty.size() == 4 {
+            println!("  mov eax, eax");
+            println!("  mov edi, edi");
+        }
+        println!("  mov edx, 0");
+        println!("  div rdi");
+    } else if op == "shl" || op == "sar" || op == "shr" {
+        println!("  mov rcx, rdi");
+        println!("  {} rax, cl", op);
     } else {
         println!("  {} rax, rdi", op);
     }
@@ -284,17 +643,24 @@ fn gen_cmp(node: Node, op: &str) {
     if let Some(rhs) = node.rhs.clone() {
         gen(*rhs);
     }
-    let op2 = match op {
-        "eq" => "e",
-        "ne" => "ne",
-        "lt" => "l",
-        "le" => "le",
-        "gt" => "l",
-        "ge" => "le",
+    // both operands are converted to the type they have in common, and
+    // unsigned ones are compared with the below and above conditions
+    let ty = cmp_type(&node);
+    let op2 = match (op, ty.is_unsigned) {
+        ("eq", _) => "e",
+        ("ne", _) => "ne",
+        ("lt" | "gt", false) => "l",
+        ("le" | "ge", false) => "le",
+        ("lt" | "gt", true) => "b",
+        ("le" | "ge", true) => "be",
         _ => "",
     };
     println!("  pop rdi");
     println!("  pop rax");
+    if ty.is_unsigned && ty.size() == 4 {
+        println!("  mov eax, eax");
+        println!("  mov edi, edi");
+    }
     if op == "gt" || op == "ge" {
         println!("  cmp rdi, rax");
     } else {
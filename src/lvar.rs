@@ -1,4 +1,13 @@
 use crate::sema::Type;
+use crate::util::find_lvar;
+
+// a piece of the initial contents of a global variable
+#[derive(Clone, Debug)]
+pub enum InitData {
+    Zero(usize),        // n zero bytes
+    Int(usize, i64),    // an integer of the given size
+    Label(String, i64), // the address of a global plus an offset
+}
 
 #[derive(Clone, Debug)]
 pub struct LVar {
@@ -6,6 +15,18 @@ pub struct LVar {
     pub name: String,
     pub offset: i32,
     pub ty: Type,
+    pub init: Vec<InitData>, // empty for zero-initialized globals
+    pub is_static: bool,     // a global only visible in this file
+    pub is_extern: bool,     // a global defined in another file
+    // a global declared without an initializer, which becomes a zero
+    // definition unless the file or another file defines it
+    pub is_tentative: bool,
+    // for a local name that stands for a global (a static local or a
+    // block-scope extern declaration), the label of that global; for a
+    // function, the symbol an asm label gave it
+    pub global: Option<String>,
+    // a name for ty declared with typedef rather than a variable
+    pub is_typedef: bool,
 }
 
 impl LVar {
@@ -15,6 +36,111 @@ impl LVar {
             name,
             offset,
             ty,
+            init: Vec::new(),
+            is_static: false,
+            is_extern: false,
+            is_tentative: false,
+            global: None,
+            is_typedef: false,
+        }
+    }
+}
+
+// the names declared in the file and in the blocks of the function being
+// parsed, with one list per block so that inner declarations shadow outer
+// ones until the block ends; global variables are kept apart, so the
+// outermost list only has typedefs
+pub struct Scope {
+    vars: Vec<Option<Box<LVar>>>,
+    // struct and union tags, which have a namespace of their own
+    tags: Vec<Vec<(String, Type)>>,
+    pub stack_size: i32, // bytes of the stack frame used by all locals so far
+}
+
+impl Scope {
+    pub fn new() -> Self {
+        Scope {
+            vars: vec![None],
+            tags: vec![Vec::new()],
+            stack_size: 0,
         }
     }
+
+    pub fn enter(&mut self) {
+        self.vars.push(None);
+        self.tags.push(Vec::new());
+    }
+
+    pub fn leave(&mut self) {
+        self.vars.pop();
+        self.tags.pop();
+    }
+
+    // how many blocks are open, so that leave_to can close those entered
+    // by code abandoned after an error
+    pub fn depth(&self) -> usize {
+        self.vars.len()
+    }
+
+    pub fn leave_to(&mut self, depth: usize) {
+        self.vars.truncate(depth);
+        self.tags.truncate(depth);
+    }
+
+    // the type the innermost declaration of name gives it, if that is a
+    // typedef rather than a variable
+    pub fn find_typedef(&self, name: &str) -> Option<Type> {
+        self.find(name)
+            .filter(|var| var.is_typedef)
+            .map(|var| var.ty)
+    }
+
+    pub fn push_typedef(&mut self, name: String, ty: Type) {
+        self.push(name, 0, ty);
+        self.vars.last_mut().unwrap().as_mut().unwrap().is_typedef = true;
+    }
+
+    pub fn find_tag(&self, name: &str) -> Option<Type> {
+        self.tags.iter().rev().find_map(|tags| find_tag(tags, name))
+    }
+
+    pub fn find_tag_in_current(&self, name: &str) -> Option<Type> {
+        find_tag(self.tags.last().unwrap(), name)
+    }
+
+    pub fn push_tag(&mut self, name: String, ty: Type) {
+        self.tags.last_mut().unwrap().push((name, ty));
+    }
+
+    // the innermost visible variable called name
+    pub fn find(&self, name: &str) -> Option<Box<LVar>> {
+        self.vars
+            .iter()
+            .rev()
+            .find_map(|vars| find_lvar(vars, name))
+    }
+
+    // a variable called name declared by the innermost block itself
+    pub fn find_in_current(&self, name: &str) -> Option<Box<LVar>> {
+        find_lvar(self.vars.last().unwrap(), name)
+    }
+
+    pub fn push(&mut self, name: String, offset: i32, ty: Type) {
+        let vars = self.vars.last_mut().unwrap();
+        *vars = Some(Box::new(LVar::new(vars.take(), name, offset, ty)));
+    }
+
+    // make name refer to the global called label until the block ends
+    pub fn push_global(&mut self, name: String, label: String, ty: Type) {
+        self.push(name, 0, ty);
+        self.vars.last_mut().unwrap().as_mut().unwrap().global = Some(label);
+    }
+}
+
+// the latest declaration of the tag called name
+fn find_tag(tags: &[(String, Type)], name: &str) -> Option<Type> {
+    tags.iter()
+        .rev()
+        .find(|(tag, _)| tag == name)
+        .map(|(_, ty)| ty.clone())
 }
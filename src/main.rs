@@ -1,65 +1,238 @@
 use std::cell::RefCell;
 use std::env;
+use std::fs;
+use std::path::Path;
 use std::process;
 use std::rc::Rc;
+use std::sync::atomic::Ordering;
 
 mod ast;
 mod codegen;
 mod lvar;
 mod parser;
+mod preprocess;
 mod sema;
 mod tokenizer;
 mod util;
 
+use lvar::{InitData, LVar};
+use sema::{Type, TypeKind};
+use util::{add_source_file, error, exit_if_errors, find_gvar_def};
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("args error");
-        process::exit(1);
-    }
+    // the parser goes on after the errors it can recover from; any other
+    // error ends compilation here
+    util::recover(|| compile(&args));
+    exit_if_errors();
+}
 
-    let tokens = &args[1][..];
-    let token = Rc::new(RefCell::new(tokenizer::tokenizer(tokens)));
-    let (codes, mut gvar) = parser::program(&mut token.borrow_mut());
+fn compile(args: &[String]) {
+    preprocess::init_macros();
+    let (input, preprocess_only) = parse_args(args);
+
+    // the input is the path of a source file, or else the source itself
+    let file = if Path::new(&input).is_file() {
+        match fs::read_to_string(&input) {
+            Ok(contents) => add_source_file(&input, contents),
+            Err(err) => error(&format!("cannot open {}: {}", input, err)),
+        }
+    } else {
+        add_source_file("<command line>", input)
+    };
+    let mut token = preprocess::preprocess(tokenizer::tokenizer(file));
+    if preprocess_only {
+        preprocess::print_tokens(&token);
+        return;
+    }
+    tokenizer::convert_keywords(&mut token);
+    let token = Rc::new(RefCell::new(token));
+    let (codes, gvar) = parser::program(&mut token.borrow_mut());
+    // nothing is emitted for a program with errors
+    exit_if_errors();
 
     println!(".intel_syntax noprefix");
 
-    println!(".section .data");
-    while let Some(current) = &gvar {
-        println!("{}:", current.name);
-        println!("  .zero {}", current.offset);
-        gvar = current.next.clone();
-    }
+    emit_data(gvar);
 
     println!(".section .text");
-    println!(".globl main");
-    for (args, code, offset, function_name) in codes {
+    for (args, code, offset, function_name, is_static) in codes {
+        if !is_static {
+            println!(".globl {}", function_name);
+        }
         println!("{}:", function_name);
         println!("  push rbp");
         println!("  mov rbp, rsp");
         println!("  sub rsp, {}", offset);
 
-        let regs = vec!["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
-        let regs_32 = vec!["edi", "esi", "edx", "ecx", "r8d", "r9d"];
-        let mut i = 0;
-        for arg in args {
+        // parameters after the sixth are on the stack above the return
+        // address, and are copied through r10
+        let regs = ["rdi", "rsi", "rdx", "rcx", "r8", "r9", "r10"];
+        let regs_32 = ["edi", "esi", "edx", "ecx", "r8d", "r9d", "r10d"];
+        let regs_16 = ["di", "si", "dx", "cx", "r8w", "r9w", "r10w"];
+        let regs_8 = ["dil", "sil", "dl", "cl", "r8b", "r9b", "r10b"];
+        for (i, arg) in args.iter().enumerate() {
+            if i >= 6 {
+                println!("  mov r10, [rbp+{}]", 16 + 8 * (i - 6));
+            }
+            let i = i.min(6);
             println!("  mov rax, rbp");
             println!("  sub rax, {}", arg.offset);
-            if arg.var_type.as_ref().unwrap().ty == sema::TypeKind::TyInt {
-                println!("  mov [rax], {}", regs_32[i]);
-            } else {
-                println!("  mov [rax], {}", regs[i]);
+            match arg.var_type.as_ref().unwrap().size() {
+                1 => println!("  mov [rax], {}", regs_8[i]),
+                2 => println!("  mov [rax], {}", regs_16[i]),
+                4 => println!("  mov [rax], {}", regs_32[i]),
+                _ => println!("  mov [rax], {}", regs[i]),
             }
-            i += 1;
         }
 
         for node in code {
             codegen::gen(node);
             println!("  pop rax");
         }
+
+        println!("  mov rsp, rbp");
+        println!("  pop rbp");
+        println!("  ret");
+    }
+}
+
+// evicc [-E] [-ferror-limit=n] [-I dir | -D name[=value] | -U name]... input
+// options are stored where they are used; the input is returned along with
+// whether -E asked to stop after preprocessing
+fn parse_args(args: &[String]) -> (String, bool) {
+    let mut input = None;
+    let mut preprocess_only = false;
+    let mut i = 1;
+    while i < args.len() {
+        let arg = &args[i];
+        if arg == "-E" {
+            preprocess_only = true;
+        } else if arg == "-I" {
+            i += 1;
+            match args.get(i) {
+                Some(dir) => preprocess::INCLUDE_PATHS.write().unwrap().push(dir.clone()),
+                None => usage(),
+            }
+        } else if let Some(dir) = arg.strip_prefix("-I") {
+            preprocess::INCLUDE_PATHS
+                .write()
+                .unwrap()
+                .push(dir.to_string());
+        } else if arg == "-D" || arg == "-U" {
+            i += 1;
+            match args.get(i) {
+                Some(name) if arg == "-D" => preprocess::define_from_command_line(name),
+                Some(name) => preprocess::undef_from_command_line(name),
+                None => usage(),
+            }
+        } else if let Some(def) = arg.strip_prefix("-D") {
+            preprocess::define_from_command_line(def);
+        } else if let Some(name) = arg.strip_prefix("-U") {
+            preprocess::undef_from_command_line(name);
+        } else if let Some(limit) = arg.strip_prefix("-ferror-limit=") {
+            match limit.parse() {
+                Ok(limit) => util::ERROR_LIMIT.store(limit, Ordering::Relaxed),
+                Err(_) => error(&format!("invalid error limit: {}", limit)),
+            }
+        } else if arg.starts_with('-') && arg.len() > 1 {
+            error(&format!("unknown argument: {}", arg));
+        } else if input.is_some() {
+            usage();
+        } else {
+            input = Some(arg.clone());
+        }
+        i += 1;
     }
+    (input.unwrap_or_else(|| usage()), preprocess_only)
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: evicc [-E] [-ferror-limit=n] [-I dir | -D name[=value] | -U name]... <file or source>"
+    );
+    process::exit(1);
+}
+
+// a global may be declared several times; emit its definition if the file
+// has one, and otherwise a single common symbol for its tentative
+// definitions, which the linker merges with those of other files
+fn emit_data(mut gvar: Option<Box<LVar>>) {
+    let all = gvar.clone();
+    let mut emitted = Vec::new();
+    while let Some(current) = gvar {
+        gvar = current.next.clone();
+        // defined in another file
+        if current.is_extern || emitted.contains(&current.name) {
+            continue;
+        }
+        if current.is_tentative && find_gvar_def(&all, &current.name).is_some() {
+            continue;
+        }
+        emitted.push(current.name.clone());
+
+        let size = current.ty.size();
+        let align = current.ty.align();
+        if current.is_tentative && !current.is_static {
+            println!(".comm {}, {}, {}", current.name, size, align);
+            continue;
+        }
+        println!(".section {}", section(&current));
+        if !current.is_static {
+            println!(".globl {}", current.name);
+        }
+        println!(".align {}", align);
+        println!("{}:", current.name);
+        if is_zero(&current) {
+            println!("  .zero {}", size);
+            continue;
+        }
+        for data in &current.init {
+            match data {
+                InitData::Zero(size) => println!("  .zero {}", size),
+                InitData::Int(1, val) => println!("  .byte {}", val),
+                InitData::Int(2, val) => println!("  .short {}", val),
+                InitData::Int(4, val) => println!("  .long {}", val),
+                InitData::Int(_, val) => println!("  .quad {}", val),
+                InitData::Label(label, 0) => println!("  .quad {}", label),
+                InitData::Label(label, addend) => println!("  .quad {}{:+}", label, addend),
+            }
+        }
+    }
+}
 
-    println!("  mov rsp, rbp");
-    println!("  pop rbp");
-    println!("  ret");
+fn section(var: &LVar) -> &'static str {
+    // string literals must not be modified either, though their type is not
+    // const
+    let is_readonly = is_readonly(&var.ty) || var.name.starts_with(".L.str.");
+    if !is_readonly && is_zero(var) {
+        ".bss"
+    } else if !is_readonly {
+        ".data"
+    } else if var
+        .init
+        .iter()
+        .any(|data| matches!(data, InitData::Label(..)))
+    {
+        // addresses are only known once the program is loaded, so the loader
+        // has to write them before the section becomes read-only
+        ".data.rel.ro, \"aw\""
+    } else {
+        ".rodata"
+    }
+}
+
+fn is_zero(var: &LVar) -> bool {
+    var.init
+        .iter()
+        .all(|data| matches!(data, InitData::Zero(_) | InitData::Int(_, 0)))
+}
+
+// an array is read-only if its elements are
+fn is_readonly(ty: &Type) -> bool {
+    if ty.ty == TypeKind::TyArray {
+        is_readonly(ty.ptr_to.as_ref().unwrap())
+    } else {
+        ty.is_const
+    }
 }
@@ -1,146 +1,924 @@
-use std::{borrow::BorrowMut, iter};
+use lazy_static::lazy_static;
+use std::borrow::BorrowMut;
+use std::sync::RwLock;
 
 use crate::ast::{
-    new_node, new_node_block, new_node_func, new_node_gvar_def, new_node_gvar_def_array,
-    new_node_lvar, new_node_num, new_node_var_def, new_node_var_def_array, Node, NodeKind,
+    new_node, new_node_assign_op, new_node_block, new_node_cast, new_node_func, new_node_gvar,
+    new_node_gvar_def, new_node_lvar, new_node_member, new_node_memzero, new_node_num,
+    new_node_post_op, new_node_var_def, Node, NodeKind,
+};
+use crate::lvar::{InitData, LVar, Scope};
+use crate::sema::{
+    add_type, arith_type, define_struct, eval, eval_reloc, is_float, new_type, new_type_array,
+    new_type_char, new_type_func, new_type_int, new_type_ptr, new_type_struct, wrap, Member, Type,
+    TypeKind,
 };
-use crate::lvar::LVar;
-use crate::sema::{add_type, TypeKind};
 use crate::tokenizer;
 use crate::util::{
-    check, consume, consume_kind, error, error_at, expect, expect_ident, expect_number,
+    align_to, brace_depth, check, check_kind, consume, consume_kind, error, error_at, expect,
+    expect_ident, expect_number, expect_string, find_gvar_def, find_lvar, gen_label, is_qualifier,
+    is_typename, recover, unwind_error,
 };
 
+lazy_static! {
+    // labels of the enclosing statements that break and continue jump out of
+    static ref BREAK_LABELS: RwLock<Vec<usize>> = RwLock::new(Vec::new());
+    static ref CONTINUE_LABELS: RwLock<Vec<usize>> = RwLock::new(Vec::new());
+    // case and default labels of the enclosing switch statements, with the
+    // type their values are converted to
+    static ref SWITCHES: RwLock<Vec<(Type, Vec<Node>)>> = RwLock::new(Vec::new());
+    // labels of the function being parsed
+    static ref GOTO_LABELS: RwLock<Vec<GotoLabel>> = RwLock::new(Vec::new());
+    // the return type of the function being parsed
+    static ref RETURN_TYPE: RwLock<Option<Box<Type>>> = RwLock::new(None);
+}
+
+// the storage class a declaration starts with
+#[derive(Clone, Copy, PartialEq)]
+enum Storage {
+    Auto,
+    Static,
+    Extern,
+    Typedef,
+}
+
+// a function definition: its parameters, body, stack frame size, name and
+// whether it is static
+pub type Function = (Vec<Node>, Vec<Node>, i32, String, bool);
+
+// a goto may jump forward, so a label is known by name before it is defined
+struct GotoLabel {
+    name: String,
+    id: usize,
+    defined: bool,
+    loc: usize, // where the label was first referenced
+}
+
+// id of the label called name in the current function
+fn goto_label(name: &str, loc: usize) -> usize {
+    let mut labels = GOTO_LABELS.write().unwrap();
+    if let Some(label) = labels.iter().find(|label| label.name == name) {
+        return label.id;
+    }
+    let id = gen_label();
+    labels.push(GotoLabel {
+        name: name.to_string(),
+        id,
+        defined: false,
+        loc,
+    });
+    id
+}
+
+fn define_goto_label(name: &str, loc: usize) -> usize {
+    let id = goto_label(name, loc);
+    let mut labels = GOTO_LABELS.write().unwrap();
+    let label = labels.iter_mut().find(|label| label.id == id).unwrap();
+    let duplicate = std::mem::replace(&mut label.defined, true);
+    // an error must not unwind while the lock is held, or it is poisoned
+    drop(labels);
+    if duplicate {
+        error_at(loc, &format!("duplicate label '{}'", name));
+    }
+    id
+}
+
+// how many statements enclosing the current one have break, continue and
+// case labels, to go back to when the parser abandons a statement after an
+// error
+fn label_depths() -> (usize, usize, usize) {
+    (
+        BREAK_LABELS.read().unwrap().len(),
+        CONTINUE_LABELS.read().unwrap().len(),
+        SWITCHES.read().unwrap().len(),
+    )
+}
+
+fn leave_labels_to((breaks, continues, switches): (usize, usize, usize)) {
+    BREAK_LABELS.write().unwrap().truncate(breaks);
+    CONTINUE_LABELS.write().unwrap().truncate(continues);
+    SWITCHES.write().unwrap().truncate(switches);
+}
+
+// every label referenced by a goto must be defined somewhere in the function
+fn check_goto_labels() {
+    let labels = std::mem::take(&mut *GOTO_LABELS.write().unwrap());
+    if let Some(label) = labels.iter().find(|label| !label.defined) {
+        error_at(
+            label.loc,
+            &format!("use of undeclared label '{}'", label.name),
+        );
+    }
+}
+
 /*
 program = (global | function)*
 */
-pub fn program(
-    token: &mut Option<Box<tokenizer::Token>>,
-) -> (Vec<(Vec<Node>, Vec<Node>, i32, String)>, Option<Box<LVar>>) {
+pub fn program(token: &mut Option<Box<tokenizer::Token>>) -> (Vec<Function>, Option<Box<LVar>>) {
     let mut code = Vec::new();
     let mut gloval_vars = None;
-    while let Some(current) = token {
-        match current.kind {
-            tokenizer::TokenKind::TkInt => {
-                global_or_function(token, &mut code, &mut gloval_vars);
-            }
-            tokenizer::TokenKind::TkEof => break,
-            _ => {
-                error_at(current.loc, "expected function");
+    // the typedefs and tags of the file, which every function sees
+    let mut scope = Scope::new();
+    while !check_kind(tokenizer::TokenKind::TkEof, token) {
+        let depth = scope.depth();
+        let braces = brace_depth();
+        let parsed = recover(|| {
+            while consume_kind(tokenizer::TokenKind::TkExtension, token) {}
+            if !is_typename(token, &scope) && !is_storage_class(token) {
+                error_at(token.as_ref().unwrap().loc, "expected function");
             }
+            global_or_function(token, &mut code, &mut scope, &mut gloval_vars);
+        });
+        if parsed.is_none() {
+            // go on with the next declaration, forgetting what was left of
+            // the one that had an error
+            scope.leave_to(depth);
+            leave_labels_to((0, 0, 0));
+            GOTO_LABELS.write().unwrap().clear();
+            synchronize(token, braces);
+            consume("}", token);
+        }
+        if token.is_none() {
+            break;
         }
     }
     return (code, gloval_vars);
 }
 
-// global_or_function = "int" "*"? ident (function | global)
+// skip the rest of the statement or declaration an error was found in, given
+// the brace depth at its start: up to and including its ";", or the "}"
+// that closes a brace it opened along with a ";" after it, or up to the "}"
+// that closes the enclosing block
+fn synchronize(token: &mut Option<Box<tokenizer::Token>>, start: isize) {
+    while token.is_some() && !check_kind(tokenizer::TokenKind::TkEof, token) {
+        let depth = brace_depth() - start;
+        if check("}", token) {
+            if depth <= 0 {
+                return;
+            }
+            consume("}", token);
+            if depth == 1 {
+                consume(";", token);
+                return;
+            }
+        } else if consume(";", token) {
+            if depth <= 0 {
+                return;
+            }
+        } else if !consume("{", token) {
+            *token = token.take().unwrap().next;
+        }
+    }
+}
+
+// a statement of a block, or a placeholder for one that had an error so
+// that the rest of the block is still checked
+fn block_stmt(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Node {
+    let depth = lvar.depth();
+    let labels = label_depths();
+    let braces = brace_depth();
+    if let Some(node) = recover(|| stmt(token, lvar, gvar)) {
+        return node;
+    }
+    lvar.leave_to(depth);
+    leave_labels_to(labels);
+    synchronize(token, braces);
+    // the rest of the file was skipped without finding the end of the block,
+    // so there is nothing left to parse in it
+    if token.is_none() || check_kind(tokenizer::TokenKind::TkEof, token) {
+        unwind_error();
+    }
+    new_node_num(0)
+}
+
+// global_or_function = declspec (";" | declarator (function | global))
 fn global_or_function(
     token: &mut Option<Box<tokenizer::Token>>,
-    code: &mut Vec<(Vec<Node>, Vec<Node>, i32, String)>,
+    code: &mut Vec<Function>,
+    lvar: &mut Scope,
     gvar: &mut Option<Box<LVar>>,
 ) {
-    if !consume_kind(tokenizer::TokenKind::TkInt, token) {
-        error_at(token.as_ref().unwrap().loc, "expected 'int'");
+    let (storage, base) = declspec(token, lvar, gvar);
+    // a struct or union declared on its own
+    if consume(";", token) {
+        return;
+    }
+    let loc = token.as_ref().unwrap().loc;
+    let (ident, ty) = declarator(token, base.clone(), lvar, gvar);
+    if storage != Storage::Typedef
+        && ty.as_ref().unwrap().ty == TypeKind::TyFunc
+        && check("{", token)
+    {
+        code.push(function(token, ident, ty, storage, lvar, gvar));
+    } else {
+        global(token, ident, loc, ty, base, storage, lvar, gvar);
+    }
+}
+
+/*
+declspec = (storage_class | qualifier | function_specifier | attribute
+            | "__extension__" | type_specifier)+
+storage_class = "static" | "extern" | "typedef"
+qualifier = "const" | "volatile" | "restrict"
+function_specifier = "inline" | "_Noreturn"
+type_specifier = "void" | "_Bool" | "char" | "short" | "int" | "long"
+               | "float" | "double" | "signed" | "unsigned"
+               | "__builtin_va_list" | ("struct" | "union") struct_decl
+               | typedef_name
+*/
+fn declspec(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> (Storage, Option<Box<Type>>) {
+    // every type specifier adds to a count, so that each combination that
+    // names a type, such as "unsigned long int", has a sum of its own
+    const VOID: u32 = 1 << 0;
+    const BOOL: u32 = 1 << 2;
+    const CHAR: u32 = 1 << 4;
+    const SHORT: u32 = 1 << 6;
+    const INT: u32 = 1 << 8;
+    const LONG: u32 = 1 << 10;
+    const FLOAT: u32 = 1 << 12;
+    const DOUBLE: u32 = 1 << 14;
+    const OTHER: u32 = 1 << 16;
+    const SIGNED: u32 = 1 << 17;
+    const UNSIGNED: u32 = 1 << 19;
+
+    let mut storage = Storage::Auto;
+    let mut counter = 0;
+    let mut ty = new_type_int();
+    // qualifiers may come before the type they qualify
+    let mut quals = new_type_int();
+    let start = token.as_ref().unwrap().loc;
+    loop {
+        let loc = token.as_ref().unwrap().loc;
+        if is_storage_class(token) {
+            if storage != Storage::Auto {
+                error_at(loc, "multiple storage classes in declaration specifiers");
+            }
+            storage = storage_class(token);
+            continue;
+        }
+        if is_qualifier(token) {
+            quals = qualifiers(token, quals);
+            continue;
+        }
+        // every function is emitted, so inline and _Noreturn change nothing
+        if consume_kind(tokenizer::TokenKind::TkInline, token)
+            || consume_kind(tokenizer::TokenKind::TkNoreturn, token)
+            || consume_kind(tokenizer::TokenKind::TkExtension, token)
+        {
+            continue;
+        }
+        if check_kind(tokenizer::TokenKind::TkAttribute, token) {
+            attributes(token);
+            continue;
+        }
+
+        // a typedef name only names a type where no other type specifier
+        // has been seen, so that it can be redeclared as something else
+        let current = token.as_ref().unwrap();
+        let typedef = match current.kind {
+            tokenizer::TokenKind::TkIdent if counter == 0 => lvar.find_typedef(&current.str),
+            _ => None,
+        };
+        let is_struct = check_kind(tokenizer::TokenKind::TkStruct, token)
+            || check_kind(tokenizer::TokenKind::TkUnion, token);
+        if typedef.is_some()
+            || is_struct
+            || check_kind(tokenizer::TokenKind::TkBuiltinVaList, token)
+        {
+            if counter != 0 {
+                error_at(loc, "invalid combination of type specifiers");
+            }
+            counter += OTHER;
+            if let Some(typedef) = typedef {
+                expect_ident(token);
+                ty = Some(Box::new(typedef));
+            } else if consume_kind(tokenizer::TokenKind::TkStruct, token) {
+                ty = struct_decl(token, TypeKind::TyStruct, lvar, gvar);
+            } else if consume_kind(tokenizer::TokenKind::TkUnion, token) {
+                ty = struct_decl(token, TypeKind::TyUnion, lvar, gvar);
+            } else {
+                consume_kind(tokenizer::TokenKind::TkBuiltinVaList, token);
+                // the register save area va_start fills in, which decays to
+                // a pointer like the array of one struct it is on x86-64
+                ty = new_type_array(new_type_char(), 24);
+                ty.as_mut().unwrap().align = 8;
+            }
+            continue;
+        }
+
+        counter += match current.kind {
+            tokenizer::TokenKind::TkVoid => VOID,
+            tokenizer::TokenKind::TkBool => BOOL,
+            tokenizer::TokenKind::TkChar => CHAR,
+            tokenizer::TokenKind::TkShort => SHORT,
+            tokenizer::TokenKind::TkInt => INT,
+            tokenizer::TokenKind::TkLong => LONG,
+            tokenizer::TokenKind::TkFloat => FLOAT,
+            tokenizer::TokenKind::TkDouble => DOUBLE,
+            tokenizer::TokenKind::TkSigned => SIGNED,
+            tokenizer::TokenKind::TkUnsigned => UNSIGNED,
+            _ => break,
+        };
+        *token = token.take().unwrap().next;
+        // signed and unsigned only say how the integer type that the other
+        // specifiers name, or int if there are none, is interpreted
+        let sign = counter & (SIGNED | UNSIGNED);
+        let (kind, size) = match counter - sign {
+            VOID => (TypeKind::TyVoid, 1),
+            BOOL => (TypeKind::TyBool, 1),
+            CHAR => (TypeKind::TyChar, 1),
+            n if n == SHORT || n == SHORT + INT => (TypeKind::TyShort, 2),
+            0 | INT => (TypeKind::TyInt, 4),
+            n if n == LONG || n == LONG + INT || n == LONG + LONG || n == LONG + LONG + INT => {
+                (TypeKind::TyLong, 8)
+            }
+            FLOAT => (TypeKind::TyFloat, 4),
+            DOUBLE => (TypeKind::TyDouble, 8),
+            n if n == LONG + DOUBLE => (TypeKind::TyLDouble, 16),
+            _ => error_at(loc, "invalid combination of type specifiers"),
+        };
+        if sign == SIGNED + UNSIGNED
+            || sign != 0
+                && !matches!(
+                    kind,
+                    TypeKind::TyChar | TypeKind::TyShort | TypeKind::TyInt | TypeKind::TyLong
+                )
+        {
+            error_at(loc, "invalid combination of type specifiers");
+        }
+        ty = new_type(kind, size, size, None, 0);
+        // plain char is signed, and _Bool only holds 0 and 1
+        ty.as_mut().unwrap().is_unsigned = counter & UNSIGNED != 0 || counter == BOOL;
+    }
+    if counter == 0 {
+        error_at(token.as_ref().unwrap().loc, "expected a type specifier");
+    }
+
+    let quals = quals.unwrap();
+    let qualified = ty.as_mut().unwrap();
+    qualified.is_const |= quals.is_const;
+    qualified.is_volatile |= quals.is_volatile;
+    if quals.is_restrict {
+        if qualified.ty != TypeKind::TyPtr {
+            error_at(start, "restrict requires a pointer type");
+        }
+        qualified.is_restrict = true;
+    }
+    (storage, ty)
+}
+
+// declaration specifiers of something that cannot have a storage class
+fn typespec(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Option<Box<Type>> {
+    let loc = token.as_ref().unwrap().loc;
+    let (storage, ty) = declspec(token, lvar, gvar);
+    if storage != Storage::Auto {
+        error_at(loc, "storage class specifier not allowed here");
+    }
+    ty
+}
+
+fn storage_class(token: &mut Option<Box<tokenizer::Token>>) -> Storage {
+    if consume_kind(tokenizer::TokenKind::TkStatic, token) {
+        Storage::Static
+    } else if consume_kind(tokenizer::TokenKind::TkExtern, token) {
+        Storage::Extern
+    } else if consume_kind(tokenizer::TokenKind::TkTypedef, token) {
+        Storage::Typedef
+    } else {
+        Storage::Auto
+    }
+}
+
+// qualifier* applied to ty
+fn qualifiers(
+    token: &mut Option<Box<tokenizer::Token>>,
+    mut ty: Option<Box<Type>>,
+) -> Option<Box<Type>> {
+    let qualified = ty.as_mut().unwrap();
+    loop {
+        if consume_kind(tokenizer::TokenKind::TkConst, token) {
+            qualified.is_const = true;
+        } else if consume_kind(tokenizer::TokenKind::TkVolatile, token) {
+            qualified.is_volatile = true;
+        } else if consume_kind(tokenizer::TokenKind::TkRestrict, token) {
+            qualified.is_restrict = true;
+        } else {
+            return ty;
+        }
+    }
+}
+
+/*
+attribute = "__attribute__" "(" "(" ... ")" ")"
+*/
+// GNU attributes are accepted where declarations may have them and ignored
+fn attributes(token: &mut Option<Box<tokenizer::Token>>) {
+    while consume_kind(tokenizer::TokenKind::TkAttribute, token) {
+        let loc = token.as_ref().unwrap().loc;
+        expect("(", token);
+        let mut depth = 1;
+        while depth > 0 {
+            if check_kind(tokenizer::TokenKind::TkEof, token) {
+                error_at(loc, "unterminated attribute");
+            }
+            if consume("(", token) {
+                depth += 1;
+            } else if consume(")", token) {
+                depth -= 1;
+            } else {
+                *token = token.take().unwrap().next;
+            }
+        }
+    }
+}
+
+/*
+asm_label = "__asm__" "(" string ")"
+*/
+// GNU extension: the symbol the declared name goes by in assembly
+fn asm_label(token: &mut Option<Box<tokenizer::Token>>) -> Option<String> {
+    if !consume_kind(tokenizer::TokenKind::TkAsm, token) {
+        return None;
+    }
+    expect("(", token);
+    let label = String::from_utf8_lossy(&expect_string(token)).into_owned();
+    expect(")", token);
+    Some(label)
+}
+
+/*
+struct_decl = attribute* ident? ("{" struct_member* "}" attribute*)?
+struct_member = declspec (";" | declarator ("," declarator)* ";")
+*/
+fn struct_decl(
+    token: &mut Option<Box<tokenizer::Token>>,
+    kind: TypeKind,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Option<Box<Type>> {
+    attributes(token);
+    let loc = token.as_ref().unwrap().loc;
+    let tag = if check_kind(tokenizer::TokenKind::TkIdent, token) {
+        Some(expect_ident(token))
+    } else {
+        None
+    };
+    if let Some(tag) = &tag {
+        if !check("{", token) {
+            // a declaration of the tag alone declares a new struct in the
+            // current scope, even if an outer scope has one
+            let found = if check(";", token) {
+                lvar.find_tag_in_current(tag)
+            } else {
+                lvar.find_tag(tag)
+            };
+            if let Some(ty) = found {
+                return Some(Box::new(ty));
+            }
+            // a struct may be referred to before it is defined, and is
+            // incomplete until then
+            let ty = new_type_struct(kind);
+            lvar.push_tag(tag.clone(), *ty.clone().unwrap());
+            return ty;
+        }
     }
-    let depth_pointer = iter::repeat(()).take_while(|_| consume("*", token)).count();
-    let ident = expect_ident(token);
+
+    // the tag is declared before the members, so that they can point to the
+    // struct, and a definition completes an earlier declaration in this scope
+    let ty = match tag.as_ref().and_then(|tag| lvar.find_tag_in_current(tag)) {
+        Some(ty) if ty.members().is_some() => {
+            error_at(loc, &format!("redefinition of '{}'", tag.unwrap()))
+        }
+        Some(ty) => Some(Box::new(ty)),
+        None => {
+            let ty = new_type_struct(kind);
+            if let Some(tag) = tag {
+                lvar.push_tag(tag, *ty.clone().unwrap());
+            }
+            ty
+        }
+    };
+    expect("{", token);
+    let mut members = Vec::new();
+    while !consume("}", token) {
+        let base = typespec(token, lvar, gvar);
+        // an anonymous struct or union, whose members belong to this one
+        if consume(";", token) {
+            members.push(Member {
+                name: String::new(),
+                ty: *base.unwrap(),
+                offset: 0,
+            });
+            continue;
+        }
+        loop {
+            let member_loc = token.as_ref().unwrap().loc;
+            let (name, ty) = declarator(token, base.clone(), lvar, gvar);
+            attributes(token);
+            if check(":", token) {
+                error_at(member_loc, "bit-fields are not supported");
+            }
+            let ty = *ty.unwrap();
+            if is_incomplete(&ty) && !(ty.ty == TypeKind::TyArray && ty.array_size == 0) {
+                error_at(member_loc, "field has incomplete type");
+            }
+            members.push(Member {
+                name,
+                ty,
+                offset: 0,
+            });
+            if consume(";", token) {
+                break;
+            }
+            expect(",", token);
+        }
+    }
+    attributes(token);
+
+    struct_type(ty.as_ref().unwrap(), members);
+    ty
+}
+
+// lay out the members of the struct or union ty one after another, each
+// aligned for its type, or all at offset 0 for a union
+fn struct_type(ty: &Type, mut members: Vec<Member>) {
+    let mut size = 0;
+    let mut align = 1;
+    for member in members.iter_mut() {
+        if ty.ty == TypeKind::TyStruct {
+            size = align_to(size as i32, member.ty.align() as i32) as usize;
+            member.offset = size;
+            size += member.ty.size();
+        } else {
+            size = size.max(member.ty.size());
+        }
+        align = align.max(member.ty.align());
+    }
+    let size = align_to(size as i32, align as i32) as usize;
+    define_struct(ty, size, align, members);
+}
+
+fn is_incomplete(ty: &Type) -> bool {
+    match ty.ty {
+        TypeKind::TyVoid => true,
+        TypeKind::TyStruct | TypeKind::TyUnion => ty.members().is_none(),
+        TypeKind::TyArray => ty.array_size == 0,
+        _ => false,
+    }
+}
+
+/*
+declarator = ("*" qualifier*)* ("(" declarator ")" | ident)? type_suffix
+*/
+// the declared name, which is empty in an abstract declarator such as the
+// type of a cast, and its type
+fn declarator(
+    token: &mut Option<Box<tokenizer::Token>>,
+    ty: Option<Box<Type>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> (String, Option<Box<Type>>) {
+    let ty = pointers(token, ty);
+    // "(" followed by a type or ")" starts the parameters of a function
+    // type instead
     if check("(", token) {
-        let func = function(token, ident, gvar);
-        code.push(func);
+        let next = &token.as_ref().unwrap().next;
+        if !is_typename(next, lvar) && !check(")", next) {
+            expect("(", token);
+            // the parenthesized part applies to the type its suffix gives,
+            // which comes after it
+            let placeholder = new_type(TypeKind::TyVoid, 1, 1, None, 0);
+            let (name, inner) = declarator(token, placeholder, lvar, gvar);
+            expect(")", token);
+            let ty = type_suffix(token, ty, lvar, gvar);
+            return (name, replace_base(inner, ty));
+        }
+    }
+    let name = if check_kind(tokenizer::TokenKind::TkIdent, token) {
+        expect_ident(token)
     } else {
-        global(token, ident, depth_pointer, gvar);
+        String::new()
+    };
+    (name, type_suffix(token, ty, lvar, gvar))
+}
+
+// ty with the type at the end of its chain of pointee, element and return
+// types replaced by base
+fn replace_base(ty: Option<Box<Type>>, base: Option<Box<Type>>) -> Option<Box<Type>> {
+    let mut ty = ty.unwrap();
+    if ty.ptr_to.is_none() {
+        return base;
+    }
+    ty.ptr_to = replace_base(ty.ptr_to.take(), base);
+    if ty.ty == TypeKind::TyArray {
+        let elem = ty.ptr_to.as_ref().unwrap();
+        ty.size = elem.size() * ty.array_size;
+        ty.align = elem.align();
+    }
+    Some(ty)
+}
+
+/*
+type_suffix = "(" func_params | "[" array_dims | ε
+*/
+fn type_suffix(
+    token: &mut Option<Box<tokenizer::Token>>,
+    ty: Option<Box<Type>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Option<Box<Type>> {
+    if consume("(", token) {
+        return func_params(token, ty, lvar, gvar);
+    }
+    if consume("[", token) {
+        return array_dims(token, ty, lvar, gvar);
+    }
+    ty
+}
+
+/*
+func_params = ("void" | param ("," param)* ("," "...")?)? ")"
+param = declspec declarator
+*/
+fn func_params(
+    token: &mut Option<Box<tokenizer::Token>>,
+    ty: Option<Box<Type>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Option<Box<Type>> {
+    if check_kind(tokenizer::TokenKind::TkVoid, token) && check(")", &token.as_ref().unwrap().next)
+    {
+        consume_kind(tokenizer::TokenKind::TkVoid, token);
+        expect(")", token);
+        return new_type_func(ty, Vec::new(), false);
+    }
+    let mut params = Vec::new();
+    let mut is_variadic = false;
+    if !consume(")", token) {
+        loop {
+            if consume("...", token) {
+                is_variadic = true;
+                expect(")", token);
+                break;
+            }
+            let base = typespec(token, lvar, gvar);
+            let (name, param) = declarator(token, base, lvar, gvar);
+            attributes(token);
+            // a parameter declared as an array or a function is a pointer
+            let param = param.unwrap();
+            let param = match param.ty {
+                TypeKind::TyArray => new_type_ptr(param.ptr_to),
+                TypeKind::TyFunc => new_type_ptr(Some(param)),
+                _ => Some(param),
+            };
+            params.push((name, *param.unwrap()));
+            if consume(")", token) {
+                break;
+            }
+            expect(",", token);
+        }
+    }
+    new_type_func(ty, params, is_variadic)
+}
+
+/*
+array_dims = ("static" | qualifier)* conditional? "]" type_suffix
+*/
+// "[]" leaves the length 0 until an initializer provides it
+fn array_dims(
+    token: &mut Option<Box<tokenizer::Token>>,
+    ty: Option<Box<Type>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Option<Box<Type>> {
+    // in a parameter these only describe the pointer it becomes
+    while consume_kind(tokenizer::TokenKind::TkStatic, token) || is_qualifier(token) {
+        qualifiers(token, new_type_int());
+    }
+    let mut size = 0;
+    if !consume("]", token) {
+        let loc = token.as_ref().unwrap().loc;
+        size = match eval(&conditional(token, lvar, gvar)) {
+            Some(size) if size >= 0 => size as usize,
+            _ => error_at(loc, "array size must be a non-negative integer constant"),
+        };
+        expect("]", token);
     }
+    let ty = type_suffix(token, ty, lvar, gvar);
+    new_type_array(ty, size)
 }
 
 /*
-global =  ("[" num "]")? ";"
+global = init_declarator ("," declarator init_declarator)* ";"
+init_declarator = asm_label? attribute* ("=" initializer)?
 */
+#[allow(clippy::too_many_arguments)]
 fn global(
+    token: &mut Option<Box<tokenizer::Token>>,
+    mut ident: String,
+    mut loc: usize,
+    mut ty: Option<Box<Type>>,
+    base: Option<Box<Type>>,
+    storage: Storage,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Node {
+    let mut defs = Vec::new();
+    loop {
+        if ident.is_empty() {
+            error_at(loc, "expected identifier");
+        }
+        let label = asm_label(token);
+        attributes(token);
+        if storage == Storage::Typedef {
+            lvar.push_typedef(ident, *ty.unwrap());
+        } else if ty.as_ref().unwrap().ty == TypeKind::TyFunc {
+            function_declaration(ident, ty, label, storage, gvar);
+        } else if label.is_some() {
+            error_at(loc, "asm labels are only supported on functions");
+        } else if storage == Storage::Extern {
+            extern_declaration(token, ident, ty, gvar);
+        } else {
+            check_var_type(&ty, loc);
+            defs.push(gvar_definition(token, ident, ty, lvar, gvar));
+            gvar.as_mut().unwrap().is_static = storage == Storage::Static;
+        }
+        if consume(";", token) {
+            return new_node_block(defs);
+        }
+        if !consume(",", token) {
+            error_at(token.as_ref().unwrap().loc, "expected ';'");
+        }
+        loc = token.as_ref().unwrap().loc;
+        (ident, ty) = declarator(token, base.clone(), lvar, gvar);
+    }
+}
+
+// define a global called ident with an optional initializer; locals in lvar
+// may appear in the initializer only as static locals
+fn gvar_definition(
     token: &mut Option<Box<tokenizer::Token>>,
     ident: String,
-    depth_pointer: usize,
+    ty: Option<Box<Type>>,
+    lvar: &mut Scope,
     gvar: &mut Option<Box<LVar>>,
 ) -> Node {
-    if consume(";", token) {
-        return new_node_gvar_def(ident, depth_pointer, gvar);
-    } else if consume("[", token) {
-        let size = expect_number(token);
-        expect("]", token);
-        expect(";", token);
-        return new_node_gvar_def_array(ident, size, gvar, TypeKind::TyInt);
+    let loc = token.as_ref().unwrap().loc;
+    if consume("=", token) {
+        if find_gvar_def(gvar, &ident).is_some() {
+            error_at(loc, &format!("redefinition of '{}'", ident));
+        }
+        let mut init = new_initializer(ty);
+        initializer(token, lvar, gvar, &mut init);
+        let def = new_node_gvar_def(ident, init.ty.clone(), gvar);
+        let mut data = Vec::new();
+        gvar_init_data(&init, &mut data);
+        gvar.as_mut().unwrap().init = data;
+        def
     } else {
-        error_at(token.as_ref().unwrap().loc, "expected ';'");
+        check_array_size(&ty, loc);
+        let def = new_node_gvar_def(ident, ty, gvar);
+        gvar.as_mut().unwrap().is_tentative = true;
+        def
     }
 }
 
+// declare a global defined in another file, unless it is already known
+fn extern_declaration(
+    token: &mut Option<Box<tokenizer::Token>>,
+    ident: String,
+    ty: Option<Box<Type>>,
+    gvar: &mut Option<Box<LVar>>,
+) {
+    if check("=", token) {
+        error_at(
+            token.as_ref().unwrap().loc,
+            "'extern' variable cannot have an initializer",
+        );
+    }
+    if find_lvar(gvar, &ident).is_none() {
+        new_node_gvar_def(ident, ty, gvar);
+        gvar.as_mut().unwrap().is_extern = true;
+    }
+}
+
+// declare a function so that calls know what it returns; it has no data,
+// so it is kept like an extern variable
+fn function_declaration(
+    ident: String,
+    ty: Option<Box<Type>>,
+    label: Option<String>,
+    storage: Storage,
+    gvar: &mut Option<Box<LVar>>,
+) {
+    if find_lvar(gvar, &ident).is_some() {
+        return;
+    }
+    new_node_gvar_def(ident, ty, gvar);
+    let func = gvar.as_mut().unwrap();
+    func.is_extern = true;
+    func.is_static = storage == Storage::Static;
+    func.global = label;
+}
+
 /*
-function = "(" ("int" "*"? ident ("," "int" "*"? ident)*)? ")" "{" stmt* "}"
+function = "{" stmt* "}"
 */
+// the definition of the function ident of type ty, whose parameters are its
+// first locals
 fn function(
     token: &mut Option<Box<tokenizer::Token>>,
     ident: String,
+    ty: Option<Box<Type>>,
+    storage: Storage,
+    lvar: &mut Scope,
     gvar: &mut Option<Box<LVar>>,
-) -> (Vec<Node>, Vec<Node>, i32, String) {
-    expect("(", token);
-    let mut lvar = None;
+) -> Function {
+    function_declaration(ident.clone(), ty.clone(), None, storage, gvar);
+    // a definition without "static" keeps the internal linkage an earlier
+    // declaration gave the function
+    let is_static = find_lvar(gvar, &ident).unwrap().is_static;
+    *RETURN_TYPE.write().unwrap() = ty.as_ref().unwrap().ptr_to.clone();
+    let loc = token.as_ref().unwrap().loc;
+    lvar.enter();
+    lvar.stack_size = 0;
     let mut args = Vec::new();
-    if !consume(")", token) {
-        if !consume_kind(tokenizer::TokenKind::TkInt, token) {
-            error_at(token.as_ref().unwrap().loc, "expected 'int'");
+    for (name, param) in &ty.unwrap().params {
+        if name.is_empty() {
+            error_at(loc, "parameter name omitted");
         }
-        let depth_pointer = iter::repeat(()).take_while(|_| consume("*", token)).count();
         args.push(new_node_var_def(
-            expect_ident(token),
-            depth_pointer,
-            &mut lvar,
+            name.clone(),
+            Some(Box::new(param.clone())),
+            lvar,
         ));
-        while consume(",", token) {
-            if !consume_kind(tokenizer::TokenKind::TkInt, token) {
-                error_at(token.as_ref().unwrap().loc, "expected 'int'");
-            }
-            let depth_pointer = iter::repeat(()).take_while(|_| consume("*", token)).count();
-            args.push(new_node_var_def(
-                expect_ident(token),
-                depth_pointer,
-                &mut lvar,
-            ));
-        }
-        expect(")", token);
     }
     expect("{", token);
     let mut stmts = Vec::new();
     while !consume("}", token) {
-        let node = stmt(token, &mut lvar, gvar);
+        let node = block_stmt(token, lvar, gvar);
         stmts.push(node.clone());
     }
-    if let Some(lvar) = lvar {
-        return (args, stmts, lvar.offset + 8, ident);
-    } else {
-        return (args, stmts, 0, ident);
-    }
+    lvar.leave();
+    check_goto_labels();
+    // the stack frame must keep rsp 16-byte aligned
+    return (args, stmts, align_to(lvar.stack_size, 16), ident, is_static);
 }
 
 /*
-stmt = expr ";"
-     | "int" "*"? ident ";"
-     | "int" ident "[" num "]" ";"
-     | "return" expr ";"
+stmt = expr? ";"
+     | declspec declaration
+     | "return" expr? ";"
      | "if" "(" expr ")" stmt ("else" stmt)?
      | "while" "(" expr ")" stmt
-     | "for" "(" expr? ";" expr? ";" expr? ")" stmt
+     | "do" stmt "while" "(" expr ")" ";"
+     | "for" "(" (expr? ";" | declspec declaration) expr? ";" expr? ")" stmt
+     | "switch" "(" expr ")" stmt
+     | "case" conditional ":" stmt
+     | "default" ":" stmt
+     | "goto" ident ";"
+     | "goto" "*" expr ";"
+     | ident ":" stmt
+     | "break" ";"
+     | "continue" ";"
      | "{" stmt* "}"
 */
 fn stmt(
     token: &mut Option<Box<tokenizer::Token>>,
-    lvar: &mut Option<Box<LVar>>,
+    lvar: &mut Scope,
     gvar: &mut Option<Box<LVar>>,
 ) -> Node {
+    let loc = token.as_ref().unwrap().loc;
+    // a null statement, which like every statement leaves a value
+    if consume(";", token) {
+        return new_node_num(0);
+    }
     if consume_kind(tokenizer::TokenKind::TkReturn, &mut token.borrow_mut()) {
-        let node = new_node(
-            NodeKind::NdReturn,
-            Some(Box::new(expr(token, lvar, gvar))),
-            None,
-        );
+        let val_loc = token.as_ref().unwrap().loc;
+        let ty = RETURN_TYPE.read().unwrap().clone();
+        // only a function returning void may return without a value
+        if check(";", token) {
+            if ty.as_ref().unwrap().ty != TypeKind::TyVoid {
+                error_at(loc, "non-void function should return a value");
+            }
+            expect(";", token);
+            return new_node(NodeKind::NdReturn, None, None);
+        }
+        let val = expr(token, lvar, gvar);
+        check_discarded_const(ty.as_ref().unwrap(), &val, val_loc, "return");
+        let node = new_node(NodeKind::NdReturn, Some(Box::new(val)), None);
         if consume(";", &mut token.borrow_mut()) {
             return node;
         } else {
@@ -168,16 +946,41 @@ fn stmt(
         expect("(", &mut token.borrow_mut());
         let cond = expr(token, lvar, gvar);
         expect(")", &mut token.borrow_mut());
-        let body = stmt(token, lvar, gvar);
-        return new_node(
+        let label = gen_label();
+        let body = loop_body(token, lvar, gvar, label);
+        let mut node = new_node(
             NodeKind::NdWhile,
             Some(Box::new(cond)),
             Some(Box::new(body)),
         );
+        node.label = label;
+        return node;
+    } else if consume_kind(tokenizer::TokenKind::TkDo, token) {
+        let label = gen_label();
+        let body = loop_body(token, lvar, gvar, label);
+        if !consume_kind(tokenizer::TokenKind::TkWhile, token) {
+            error_at(token.as_ref().unwrap().loc, "expected 'while'");
+        }
+        expect("(", token);
+        let cond = expr(token, lvar, gvar);
+        expect(")", token);
+        expect(";", token);
+        let mut node = new_node(
+            NodeKind::NdDoWhile,
+            Some(Box::new(body)),
+            Some(Box::new(cond)),
+        );
+        node.label = label;
+        return node;
     } else if consume_kind(tokenizer::TokenKind::TkFor, &mut token.borrow_mut()) {
         expect("(", &mut token.borrow_mut());
+        // a variable declared in the loop header is scoped to the loop
+        lvar.enter();
         let init = if consume(";", token) {
             None
+        } else if is_typename(token, lvar) {
+            let base = typespec(token, lvar, gvar);
+            Some(declaration(token, Storage::Auto, base, lvar, gvar))
         } else {
             let result = expr(token, lvar, gvar);
             expect(";", &mut token.borrow_mut());
@@ -197,89 +1000,989 @@ fn stmt(
             expect(")", &mut token.borrow_mut());
             Some(result)
         };
-        let body = stmt(token, lvar, gvar);
-        return new_node(
+        let label = gen_label();
+        let body = loop_body(token, lvar, gvar, label);
+        let mut node = new_node(
             NodeKind::NdFor,
             init.map(Box::new),
             Some(Box::new(new_node(
-                NodeKind::NdFor,
-                cond.map(Box::new),
-                Some(Box::new(new_node(
-                    NodeKind::NdFor,
-                    inc.map(Box::new),
-                    Some(Box::new(body)),
-                ))),
+                NodeKind::NdFor,
+                cond.map(Box::new),
+                Some(Box::new(new_node(
+                    NodeKind::NdFor,
+                    inc.map(Box::new),
+                    Some(Box::new(body)),
+                ))),
+            ))),
+        );
+        node.label = label;
+        lvar.leave();
+        return node;
+    } else if consume_kind(tokenizer::TokenKind::TkSwitch, token) {
+        expect("(", token);
+        let cond = expr(token, lvar, gvar);
+        expect(")", token);
+        // the controlling expression is promoted like an operand
+        let ty = cond.var_type.as_ref().unwrap();
+        let ty = arith_type(ty, ty);
+        let cond = new_node_cast(cond, ty.clone());
+        let label = gen_label();
+        BREAK_LABELS.write().unwrap().push(label);
+        SWITCHES.write().unwrap().push((*ty.unwrap(), Vec::new()));
+        let body = stmt(token, lvar, gvar);
+        BREAK_LABELS.write().unwrap().pop();
+        let (_, cases) = SWITCHES.write().unwrap().pop().unwrap();
+        let mut node = new_node(
+            NodeKind::NdSwitch,
+            Some(Box::new(cond)),
+            Some(Box::new(body)),
+        );
+        node.label = label;
+        node.stmts = cases;
+        return node;
+    } else if consume_kind(tokenizer::TokenKind::TkCase, token) {
+        let val_loc = token.as_ref().unwrap().loc;
+        let val = match eval(&conditional(token, lvar, gvar)) {
+            Some(val) => val,
+            None => error_at(val_loc, "expected a constant expression"),
+        };
+        expect(":", token);
+        let mut node = new_node(NodeKind::NdCase, None, None);
+        node.label = gen_label();
+        // the lock is released before an error unwinds, which would
+        // otherwise poison it for the code parsed after recovering
+        let error = match SWITCHES.write().unwrap().last_mut() {
+            Some((ty, cases)) => {
+                // the value is compared as the type of the controlling
+                // expression, so it is converted to it
+                node.val = wrap(val, ty);
+                if cases
+                    .iter()
+                    .any(|case| matches!(case.kind, NodeKind::NdCase) && case.val == node.val)
+                {
+                    Some((val_loc, format!("duplicate case value '{}'", val)))
+                } else {
+                    cases.push(node.clone());
+                    None
+                }
+            }
+            None => Some((loc, "case label not within a switch statement".to_string())),
+        };
+        if let Some((loc, msg)) = error {
+            error_at(loc, &msg);
+        }
+        node.lhs = Some(Box::new(stmt(token, lvar, gvar)));
+        return node;
+    } else if consume_kind(tokenizer::TokenKind::TkDefault, token) {
+        expect(":", token);
+        let mut node = new_node(NodeKind::NdDefault, None, None);
+        node.label = gen_label();
+        let error = match SWITCHES.write().unwrap().last_mut() {
+            Some((_, cases))
+                if cases
+                    .iter()
+                    .any(|case| matches!(case.kind, NodeKind::NdDefault)) =>
+            {
+                Some("multiple default labels in one switch")
+            }
+            Some((_, cases)) => {
+                cases.push(node.clone());
+                None
+            }
+            None => Some("default label not within a switch statement"),
+        };
+        if let Some(msg) = error {
+            error_at(loc, msg);
+        }
+        node.lhs = Some(Box::new(stmt(token, lvar, gvar)));
+        return node;
+    } else if consume_kind(tokenizer::TokenKind::TkGoto, token) {
+        if consume("*", token) {
+            let node = new_node(
+                NodeKind::NdGotoExpr,
+                Some(Box::new(expr(token, lvar, gvar))),
+                None,
+            );
+            expect(";", token);
+            return node;
+        }
+        let ident_loc = token.as_ref().unwrap().loc;
+        let mut node = new_node(NodeKind::NdGoto, None, None);
+        node.label = goto_label(&expect_ident(token), ident_loc);
+        expect(";", token);
+        return node;
+    } else if is_label(token) {
+        let mut node = new_node(NodeKind::NdLabel, None, None);
+        node.label = define_goto_label(&expect_ident(token), loc);
+        expect(":", token);
+        node.lhs = Some(Box::new(stmt(token, lvar, gvar)));
+        return node;
+    } else if consume_kind(tokenizer::TokenKind::TkBreak, token) {
+        let mut node = new_node(NodeKind::NdBreak, None, None);
+        node.label = match BREAK_LABELS.read().unwrap().last() {
+            Some(&label) => label,
+            None => error_at(loc, "break statement not within loop or switch"),
+        };
+        expect(";", token);
+        return node;
+    } else if consume_kind(tokenizer::TokenKind::TkContinue, token) {
+        let mut node = new_node(NodeKind::NdContinue, None, None);
+        node.label = match CONTINUE_LABELS.read().unwrap().last() {
+            Some(&label) => label,
+            None => error_at(loc, "continue statement not within loop"),
+        };
+        expect(";", token);
+        return node;
+    } else if is_typename(token, lvar) || is_storage_class(token) {
+        let (storage, base) = declspec(token, lvar, gvar);
+        return declaration(token, storage, base, lvar, gvar);
+    } else if consume("{", token) {
+        let mut stmts = Vec::new();
+        lvar.enter();
+        while !consume("}", token) {
+            stmts.push(block_stmt(token, lvar, gvar));
+        }
+        lvar.leave();
+        return new_node_block(stmts);
+    }
+    let node = expr(token, lvar, gvar);
+    if consume(";", &mut token.borrow_mut()) {
+        return node;
+    } else {
+        if let Some(current) = token.borrow_mut() {
+            error_at(current.loc, "expected ';'");
+        } else {
+            error("unexpected error");
+        }
+    }
+}
+
+fn is_storage_class(token: &Option<Box<tokenizer::Token>>) -> bool {
+    if let Some(current) = token {
+        return current.kind == tokenizer::TokenKind::TkStatic
+            || current.kind == tokenizer::TokenKind::TkExtern
+            || current.kind == tokenizer::TokenKind::TkTypedef;
+    }
+    false
+}
+
+// true if the next tokens are ident ":"
+fn is_label(token: &Option<Box<tokenizer::Token>>) -> bool {
+    if let Some(current) = token {
+        return current.kind == tokenizer::TokenKind::TkIdent && check(":", &current.next);
+    }
+    false
+}
+
+// parse a loop body in which break and continue refer to the loop's labels
+fn loop_body(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+    label: usize,
+) -> Node {
+    BREAK_LABELS.write().unwrap().push(label);
+    CONTINUE_LABELS.write().unwrap().push(label);
+    let body = stmt(token, lvar, gvar);
+    BREAK_LABELS.write().unwrap().pop();
+    CONTINUE_LABELS.write().unwrap().pop();
+    body
+}
+
+/*
+declaration = ";" | declarator init_declarator ("," declarator init_declarator)* ";"
+*/
+fn declaration(
+    token: &mut Option<Box<tokenizer::Token>>,
+    storage: Storage,
+    base: Option<Box<Type>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Node {
+    let mut defs = Vec::new();
+    // a struct or union declared on its own
+    if consume(";", token) {
+        return new_node_num(0);
+    }
+    loop {
+        let (ident, ty) = declarator(token, base.clone(), lvar, gvar);
+        let loc = token.as_ref().unwrap().loc;
+        if ident.is_empty() {
+            error_at(loc, "expected identifier");
+        }
+        let label = asm_label(token);
+        attributes(token);
+        if (storage == Storage::Static || storage == Storage::Extern)
+            && lvar.find_in_current(&ident).is_some()
+        {
+            error_at(loc, "variable already declared");
+        }
+        if storage == Storage::Typedef {
+            lvar.push_typedef(ident, *ty.unwrap());
+        } else if ty.as_ref().unwrap().ty == TypeKind::TyFunc {
+            // a function declared in a block is still a global
+            function_declaration(ident, ty, label, storage, gvar);
+        } else if label.is_some() {
+            error_at(loc, "asm labels are only supported on functions");
+        } else if storage == Storage::Static {
+            check_var_type(&ty, loc);
+            // a static local is a global with a name no other symbol can have
+            let label = format!("{}.{}", ident, gen_label());
+            let def = gvar_definition(token, label.clone(), ty, lvar, gvar);
+            let gvar = gvar.as_mut().unwrap();
+            gvar.is_static = true;
+            lvar.push_global(ident, label, gvar.ty.clone());
+            defs.push(def);
+        } else if storage == Storage::Extern {
+            extern_declaration(token, ident.clone(), ty, gvar);
+            let ty = find_lvar(gvar, &ident).unwrap().ty;
+            lvar.push_global(ident.clone(), ident, ty);
+        } else if consume("=", token) {
+            check_var_type(&ty, loc);
+            let mut init = new_initializer(ty);
+            initializer(token, lvar, gvar, &mut init);
+            defs.extend(lvar_initializer(ident, &init, lvar, gvar));
+        } else {
+            check_var_type(&ty, loc);
+            check_array_size(&ty, loc);
+            defs.push(new_node_var_def(ident, ty, lvar));
+        }
+        if consume(";", token) {
+            break;
+        }
+        if !consume(",", token) {
+            error_at(token.as_ref().unwrap().loc, "expected ';'");
+        }
+    }
+    match defs.len() {
+        // like every statement, a declaration that generates no code still
+        // has to leave a value on the stack
+        0 => new_node_num(0),
+        1 => defs.pop().unwrap(),
+        _ => new_node_block(defs),
+    }
+}
+
+// ("*" qualifier*)* in front of the name of a declarator
+fn pointers(
+    token: &mut Option<Box<tokenizer::Token>>,
+    mut ty: Option<Box<Type>>,
+) -> Option<Box<Type>> {
+    while consume("*", token) {
+        ty = qualifiers(token, new_type_ptr(ty));
+    }
+    ty
+}
+
+// a variable must have a complete type that code can be generated for
+fn check_var_type(ty: &Option<Box<Type>>, loc: usize) {
+    let mut elem = ty.as_ref().unwrap();
+    while elem.ty == TypeKind::TyArray {
+        elem = elem.ptr_to.as_ref().unwrap();
+    }
+    if is_float(elem) {
+        error_at(loc, "floating-point types are not supported");
+    }
+    if elem.ty == TypeKind::TyVoid
+        || (matches!(elem.ty, TypeKind::TyStruct | TypeKind::TyUnion) && elem.members().is_none())
+    {
+        error_at(loc, "variable has incomplete type");
+    }
+}
+
+fn check_array_size(ty: &Option<Box<Type>>, loc: usize) {
+    let ty = ty.as_ref().unwrap();
+    if ty.ty == TypeKind::TyArray && ty.array_size == 0 {
+        error_at(loc, "array size missing");
+    }
+}
+
+// an initializer laid out after the type it initializes: arrays have one
+// child per element, structs and unions one per member, and scalars without
+// an expression are zero
+struct Initializer {
+    ty: Option<Box<Type>>,
+    expr: Option<Node>,
+    loc: usize,
+    children: Vec<Initializer>,
+}
+
+fn new_initializer(ty: Option<Box<Type>>) -> Initializer {
+    let mut children = Vec::new();
+    let elem = &ty.as_ref().unwrap();
+    if elem.ty == TypeKind::TyArray {
+        for _ in 0..elem.array_size {
+            children.push(new_initializer(elem.ptr_to.clone()));
+        }
+    }
+    if matches!(elem.ty, TypeKind::TyStruct | TypeKind::TyUnion) {
+        for member in elem.members().unwrap_or_default() {
+            children.push(new_initializer(Some(Box::new(member.ty.clone()))));
+        }
+    }
+    Initializer {
+        ty,
+        expr: None,
+        loc: 0,
+        children,
+    }
+}
+
+/*
+initializer = assign
+            | "{" (designation? initializer ("," designation? initializer)*)? ","? "}"
+designation = "[" conditional "]" "=" | "." ident "="
+*/
+fn initializer(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+    init: &mut Initializer,
+) {
+    let ty = init.ty.clone().unwrap();
+    if matches!(ty.ty, TypeKind::TyStruct | TypeKind::TyUnion) {
+        if check("{", token) {
+            struct_initializer(token, lvar, gvar, init);
+        } else {
+            // a struct may also be initialized with another one
+            init.loc = token.as_ref().unwrap().loc;
+            init.expr = Some(assign(token, lvar, gvar));
+        }
+        return;
+    }
+    // a char array may be initialized with a string literal
+    if ty.ty == TypeKind::TyArray
+        && ty.ptr_to.as_ref().unwrap().ty == TypeKind::TyChar
+        && check_kind(tokenizer::TokenKind::TkStr, token)
+    {
+        string_initializer(token, init);
+        return;
+    }
+    if ty.ty != TypeKind::TyArray {
+        // a scalar may be wrapped in braces
+        let braced = consume("{", token);
+        init.loc = token.as_ref().unwrap().loc;
+        let expr = assign(token, lvar, gvar);
+        check_discarded_const(&ty, &expr, init.loc, "initialization");
+        init.expr = Some(expr);
+        if braced {
+            consume(",", token);
+            expect("}", token);
+        }
+        return;
+    }
+
+    // an array declared with "[]" grows to fit its initializer
+    let flexible = ty.array_size == 0;
+    expect("{", token);
+    let mut index = 0;
+    while !consume("}", token) {
+        let loc = token.as_ref().unwrap().loc;
+        if consume("[", token) {
+            index = match eval(&conditional(token, lvar, gvar)) {
+                Some(val) if val >= 0 => val as usize,
+                _ => error_at(loc, "expected a non-negative constant array index"),
+            };
+            expect("]", token);
+            expect("=", token);
+        } else if check(".", token) {
+            error_at(
+                loc,
+                "field designator in an initializer for a non-struct type",
+            );
+        }
+        if flexible {
+            while init.children.len() <= index {
+                init.children.push(new_initializer(ty.ptr_to.clone()));
+            }
+        }
+        if index >= init.children.len() {
+            error_at(loc, "excess elements in array initializer");
+        }
+        list_element(token, lvar, gvar, &mut init.children[index]);
+        index += 1;
+        if !consume(",", token) {
+            expect("}", token);
+            break;
+        }
+    }
+    if flexible {
+        init.ty = new_type_array(ty.ptr_to.clone(), init.children.len());
+    }
+}
+
+// an element of a braced list; a struct, union or array without braces of its
+// own takes as many of the elements that follow as it has scalars, unless it
+// is a struct or union initialized with another one
+fn list_element(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+    init: &mut Initializer,
+) {
+    let ty = init.ty.clone().unwrap();
+    if !is_aggregate(&ty)
+        || check("{", token)
+        || (ty.ty == TypeKind::TyArray && check_kind(tokenizer::TokenKind::TkStr, token))
+    {
+        initializer(token, lvar, gvar, init);
+        return;
+    }
+    let loc = token.as_ref().unwrap().loc;
+    let first = assign(token, lvar, gvar);
+    if is_whole_value(&ty, &first) {
+        init.loc = loc;
+        init.expr = Some(first);
+        return;
+    }
+    elided_initializer(token, lvar, gvar, init, &mut Some((loc, first)));
+}
+
+// fill init from the elements of the enclosing list, starting with the one
+// already parsed as first, until it is full or the list has no more
+fn elided_initializer(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+    init: &mut Initializer,
+    first: &mut Option<(usize, Node)>,
+) {
+    let ty = init.ty.clone().unwrap();
+    let count = if ty.ty == TypeKind::TyUnion {
+        1
+    } else {
+        init.children.len()
+    };
+    for index in 0..count {
+        if first.is_none() && !next_element(token) {
+            return;
+        }
+        let loc = token.as_ref().unwrap().loc;
+        let child = if ty.ty == TypeKind::TyArray {
+            &mut init.children[index]
+        } else {
+            select_member(init, index, loc)
+        };
+        match first.take() {
+            None => list_element(token, lvar, gvar, child),
+            Some((loc, expr)) if is_whole_value(child.ty.as_ref().unwrap(), &expr) => {
+                check_discarded_const(child.ty.as_ref().unwrap(), &expr, loc, "initialization");
+                child.loc = loc;
+                child.expr = Some(expr);
+            }
+            expr => {
+                *first = expr;
+                elided_initializer(token, lvar, gvar, child, first);
+            }
+        }
+    }
+}
+
+// whether another element follows in the list, whose "," is then consumed; a
+// designator goes back to the object the braces belong to
+fn next_element(token: &mut Option<Box<tokenizer::Token>>) -> bool {
+    if !check(",", token) {
+        return false;
+    }
+    let next = &token.as_ref().unwrap().next;
+    if check("}", next) || check("[", next) || check(".", next) {
+        return false;
+    }
+    consume(",", token)
+}
+
+// whether expr initializes all of an object of type ty, as a scalar or a
+// struct of the same type does, rather than the first scalar in it
+fn is_whole_value(ty: &Type, expr: &Node) -> bool {
+    !is_aggregate(ty)
+        || (ty.layout.is_some() && expr.var_type.as_ref().unwrap().layout == ty.layout)
+}
+
+fn is_aggregate(ty: &Type) -> bool {
+    matches!(
+        ty.ty,
+        TypeKind::TyArray | TypeKind::TyStruct | TypeKind::TyUnion
+    )
+}
+
+// the members of a struct in order, or of a union only the first, unless a
+// designator names the one to go on from
+fn struct_initializer(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+    init: &mut Initializer,
+) {
+    let ty = init.ty.clone().unwrap();
+    let kind = if ty.ty == TypeKind::TyUnion {
+        "union"
+    } else {
+        "struct"
+    };
+    let members = ty.members().unwrap();
+    expect("{", token);
+    let mut index = 0;
+    while !consume("}", token) {
+        let loc = token.as_ref().unwrap().loc;
+        if check("[", token) {
+            error_at(
+                loc,
+                "array designator in an initializer for a non-array type",
+            );
+        }
+        if consume(".", token) {
+            let name = expect_ident(token);
+            index = member_initializer(token, lvar, gvar, init, &name, loc);
+        } else {
+            if index >= members.len() || (ty.ty == TypeKind::TyUnion && index > 0) {
+                error_at(loc, &format!("excess elements in {} initializer", kind));
+            }
+            let child = select_member(init, index, loc);
+            list_element(token, lvar, gvar, child);
+        }
+        index += 1;
+        if !consume(",", token) {
+            expect("}", token);
+            break;
+        }
+    }
+}
+
+// the initializer after a designator for the member called name, which may
+// be in one of the anonymous members of init; the index of the member of
+// init that it initializes is returned
+fn member_initializer(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+    init: &mut Initializer,
+    name: &str,
+    loc: usize,
+) -> usize {
+    let ty = init.ty.clone().unwrap();
+    let members = ty.members().unwrap();
+    let index = match members.iter().position(|member| {
+        member.name == name
+            || (member.name.is_empty()
+                && find_member(&member.ty.members().unwrap_or_default(), name).is_some())
+    }) {
+        Some(index) => index,
+        None => error_at(
+            loc,
+            &format!("field designator '{}' does not refer to any field", name),
+        ),
+    };
+    let child = select_member(init, index, loc);
+    if members[index].name.is_empty() {
+        member_initializer(token, lvar, gvar, child, name, loc);
+    } else {
+        expect("=", token);
+        list_element(token, lvar, gvar, child);
+    }
+    index
+}
+
+// the initializer of the member at index; a union has only one member
+// initialized, so the others are cleared
+fn select_member(init: &mut Initializer, index: usize, loc: usize) -> &mut Initializer {
+    let ty = init.ty.clone().unwrap();
+    let members = ty.members().unwrap();
+    let member_ty = &members[index].ty;
+    if member_ty.ty == TypeKind::TyArray && member_ty.array_size == 0 {
+        error_at(
+            loc,
+            "initialization of flexible array member is not allowed",
+        );
+    }
+    if ty.ty == TypeKind::TyUnion {
+        for (i, member) in members.iter().enumerate() {
+            if i != index {
+                init.children[i] = new_initializer(Some(Box::new(member.ty.clone())));
+            }
+        }
+    }
+    &mut init.children[index]
+}
+
+// each char of the array initialized with a byte of the string, including
+// its nul if the array has room for it
+fn string_initializer(token: &mut Option<Box<tokenizer::Token>>, init: &mut Initializer) {
+    let loc = token.as_ref().unwrap().loc;
+    let bytes = string_contents_joined(token);
+    let ty = init.ty.clone().unwrap();
+    if ty.array_size == 0 {
+        init.ty = new_type_array(ty.ptr_to.clone(), bytes.len());
+        init.children = (0..bytes.len())
+            .map(|_| new_initializer(ty.ptr_to.clone()))
+            .collect();
+    }
+    if bytes.len() - 1 > init.children.len() {
+        error_at(loc, "initializer-string for char array is too long");
+    }
+    for (child, &byte) in init.children.iter_mut().zip(&bytes) {
+        child.loc = loc;
+        child.expr = Some(new_node_num(byte as i8 as i64));
+    }
+}
+
+// the definition of a local followed by the assignments that initialize it
+fn lvar_initializer(
+    name: String,
+    init: &Initializer,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Vec<Node> {
+    let def = new_node_var_def(name.clone(), init.ty.clone(), lvar);
+    let mut nodes = Vec::new();
+    if !init.children.is_empty() {
+        // elements without an initializer are zero
+        nodes.push(new_node_memzero(&def));
+    }
+    nodes.insert(0, def);
+    init_assignments(init, new_node_lvar(name, init.loc, lvar, gvar), &mut nodes);
+    nodes
+}
+
+fn init_assignments(init: &Initializer, target: Node, nodes: &mut Vec<Node>) {
+    let ty = init.ty.as_ref().unwrap();
+    for (i, child) in init.children.iter().enumerate() {
+        if let Some(members) = ty.members() {
+            init_assignments(child, new_node_member(target.clone(), &members[i]), nodes);
+            continue;
+        }
+        let elem = new_node(
+            NodeKind::NdDeref,
+            None,
+            Some(Box::new(new_node(
+                NodeKind::NdAdd,
+                Some(Box::new(target.clone())),
+                Some(Box::new(new_node_num(i as i64))),
             ))),
         );
-    } else if consume_kind(tokenizer::TokenKind::TkInt, &mut token.borrow_mut()) {
-        let depth_pointer = iter::repeat(()).take_while(|_| consume("*", token)).count();
-        let ident = expect_ident(token);
-        if consume(";", token) {
-            return new_node_var_def(ident, depth_pointer, lvar);
-        } else if consume("[", token) {
-            assert!(depth_pointer == 0);
-            let size = expect_number(token);
-            expect("]", token);
-            expect(";", token);
-            return new_node_var_def_array(ident, size, lvar, TypeKind::TyInt);
-        } else {
-            error_at(token.as_ref().unwrap().loc, "expected ';'");
+        init_assignments(child, elem, nodes);
+    }
+    if let Some(expr) = &init.expr {
+        nodes.push(new_node(
+            NodeKind::NdAssign,
+            Some(Box::new(target)),
+            Some(Box::new(expr.clone())),
+        ));
+    }
+}
+
+// the contents of a global variable, which must be known at compile time
+fn gvar_init_data(init: &Initializer, data: &mut Vec<InitData>) {
+    let ty = init.ty.as_ref().unwrap();
+    if ty.ty == TypeKind::TyArray {
+        for child in &init.children {
+            gvar_init_data(child, data);
         }
-    } else if consume("{", token) {
-        let mut stmts = Vec::new();
-        while !consume("}", token) {
-            stmts.push(stmt(token, lvar, gvar));
+        return;
+    }
+    // a struct initialized with another struct is not a constant, which
+    // the scalar case below reports
+    if let (Some(members), None) = (ty.members(), &init.expr) {
+        // members are placed at their offsets, with zero padding between
+        // them; only the initialized member of a union has data
+        let mut offset = 0;
+        for (member, child) in members.iter().zip(&init.children) {
+            if ty.ty == TypeKind::TyUnion && !has_value(child) {
+                continue;
+            }
+            if member.offset > offset {
+                data.push(InitData::Zero(member.offset - offset));
+            }
+            gvar_init_data(child, data);
+            offset = member.offset + member.ty.size();
         }
-        return new_node_block(stmts);
+        if ty.size() > offset {
+            data.push(InitData::Zero(ty.size() - offset));
+        }
+        return;
     }
-    let node = expr(token, lvar, gvar);
-    if consume(";", &mut token.borrow_mut()) {
-        return node;
-    } else {
-        if let Some(current) = token.borrow_mut() {
-            error_at(current.loc, "expected ';'");
-        } else {
-            error("unexpected error");
+    let expr = match &init.expr {
+        Some(expr) => expr,
+        None => {
+            data.push(InitData::Zero(ty.size()));
+            return;
         }
+    };
+    let mut label = None;
+    let val = match eval_reloc(expr, &mut label) {
+        Some(val) => val,
+        None => error_at(
+            init.loc,
+            "initializer element is not a compile-time constant",
+        ),
+    };
+    match label {
+        Some(label) if ty.ty == TypeKind::TyPtr => data.push(InitData::Label(label, val)),
+        Some(_) => error_at(
+            init.loc,
+            "initializer element is not computable at load time",
+        ),
+        None => data.push(InitData::Int(ty.size(), wrap(val, ty))),
     }
 }
 
+// whether any part of init was given a value
+fn has_value(init: &Initializer) -> bool {
+    init.expr.is_some() || init.children.iter().any(has_value)
+}
+
 /*
-expr = assign
+expr = assign ("," expr)?
 */
 fn expr(
     token: &mut Option<Box<tokenizer::Token>>,
-    lvar: &mut Option<Box<LVar>>,
+    lvar: &mut Scope,
     gvar: &mut Option<Box<LVar>>,
 ) -> Node {
-    return assign(token, lvar, gvar);
+    let node = assign(token, lvar, gvar);
+    if consume(",", token) {
+        return new_node(
+            NodeKind::NdComma,
+            Some(Box::new(node)),
+            Some(Box::new(expr(token, lvar, gvar))),
+        );
+    }
+    node
 }
 
 /*
-assign = equality ("=" assign)?
+assign = conditional (assign_op assign)?
+assign_op = "=" | "+=" | "-=" | "*=" | "/=" | "%=" | "&=" | "|=" | "^=" | "<<=" | ">>="
 */
 fn assign(
     token: &mut Option<Box<tokenizer::Token>>,
-    lvar: &mut Option<Box<LVar>>,
+    lvar: &mut Scope,
     gvar: &mut Option<Box<LVar>>,
 ) -> Node {
-    let node = equality(token, lvar, gvar);
+    let node = conditional(token, lvar, gvar);
+    let loc = token.as_ref().unwrap().loc;
     if consume("=", &mut token.borrow_mut()) {
+        check_assignable(&node, loc);
+        let rhs = assign(token, lvar, gvar);
+        check_discarded_const(node.var_type.as_ref().unwrap(), &rhs, loc, "assignment");
         return new_node(
             NodeKind::NdAssign,
             Some(Box::new(node)),
-            Some(Box::new(assign(token, lvar, gvar))),
+            Some(Box::new(rhs)),
         );
     }
+    let assign_ops = [
+        ("+=", NodeKind::NdAdd),
+        ("-=", NodeKind::NdSub),
+        ("*=", NodeKind::NdMul),
+        ("/=", NodeKind::NdDiv),
+        ("%=", NodeKind::NdMod),
+        ("&=", NodeKind::NdBitAnd),
+        ("|=", NodeKind::NdBitOr),
+        ("^=", NodeKind::NdBitXor),
+        ("<<=", NodeKind::NdShl),
+        (">>=", NodeKind::NdShr),
+    ];
+    for (op, kind) in assign_ops {
+        if consume(op, token) {
+            check_assignable(&node, loc);
+            return new_node_assign_op(kind, node, assign(token, lvar, gvar));
+        }
+    }
     return node;
 }
 
+fn check_assignable(node: &Node, loc: usize) {
+    let ty = node.var_type.as_ref().unwrap();
+    if ty.is_const {
+        error_at(loc, "cannot assign to variable with const-qualified type");
+    }
+    if has_const_member(ty) {
+        error_at(loc, "cannot assign to variable with const-qualified member");
+    }
+}
+
+// whether assigning a whole struct or union of type ty would write to a
+// const member of it, or of a struct, union or array in it
+fn has_const_member(ty: &Type) -> bool {
+    ty.members().unwrap_or_default().iter().any(|member| {
+        let mut elem = &member.ty;
+        while elem.ty == TypeKind::TyArray {
+            elem = elem.ptr_to.as_ref().unwrap();
+        }
+        elem.is_const || has_const_member(elem)
+    })
+}
+
+// a pointer to const must not silently become a pointer to non-const when
+// rhs is converted to ty by what
+fn check_discarded_const(ty: &Type, rhs: &Node, loc: usize, what: &str) {
+    let rty = rhs.var_type.as_ref().unwrap();
+    // integers, including null pointer constants, point to nothing
+    if ty.ty != TypeKind::TyPtr || rty.ptr_to.is_none() {
+        return;
+    }
+    if rty.ptr_to.as_ref().unwrap().is_const && !ty.ptr_to.as_ref().unwrap().is_const {
+        error_at(
+            loc,
+            &format!(
+                "{} discards 'const' qualifier from pointer target type",
+                what
+            ),
+        );
+    }
+}
+
+// the value of the constant expression of a preprocessor #if, in which
+// every identifier has already been replaced
+pub fn const_expr(token: &mut Option<Box<tokenizer::Token>>) -> i64 {
+    let loc = token.as_ref().unwrap().loc;
+    let node = conditional(token, &mut Scope::new(), &mut None);
+    if !check_kind(tokenizer::TokenKind::TkEof, token) {
+        error_at(token.as_ref().unwrap().loc, "missing binary operator");
+    }
+    match eval(&node) {
+        Some(val) => val,
+        None => error_at(loc, "invalid constant expression"),
+    }
+}
+
+/*
+conditional = logor ("?" expr ":" conditional)?
+*/
+fn conditional(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Node {
+    let cond = logor(token, lvar, gvar);
+    let loc = token.as_ref().unwrap().loc;
+    if !consume("?", token) {
+        return cond;
+    }
+    let then = expr(token, lvar, gvar);
+    expect(":", token);
+    let els = conditional(token, lvar, gvar);
+    let node = new_node(
+        NodeKind::NdCond,
+        Some(Box::new(cond)),
+        Some(Box::new(new_node(
+            NodeKind::NdElse,
+            Some(Box::new(then)),
+            Some(Box::new(els)),
+        ))),
+    );
+    if node.var_type.is_none() {
+        error_at(loc, "incompatible operand types in conditional expression");
+    }
+    node
+}
+
+/*
+logor = logand ("||" logand)*
+*/
+fn logor(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Node {
+    let mut node = logand(token, lvar, gvar);
+
+    while consume("||", token) {
+        node = new_node(
+            NodeKind::NdLogOr,
+            Some(Box::new(node)),
+            Some(Box::new(logand(token, lvar, gvar))),
+        );
+    }
+    node
+}
+
+/*
+logand = bitor ("&&" bitor)*
+*/
+fn logand(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Node {
+    let mut node = bitor(token, lvar, gvar);
+
+    while consume("&&", token) {
+        node = new_node(
+            NodeKind::NdLogAnd,
+            Some(Box::new(node)),
+            Some(Box::new(bitor(token, lvar, gvar))),
+        );
+    }
+    node
+}
+
+/*
+bitor = bitxor ("|" bitxor)*
+*/
+fn bitor(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Node {
+    let mut node = bitxor(token, lvar, gvar);
+
+    while consume("|", token) {
+        node = new_node(
+            NodeKind::NdBitOr,
+            Some(Box::new(node)),
+            Some(Box::new(bitxor(token, lvar, gvar))),
+        );
+    }
+    node
+}
+
+/*
+bitxor = bitand ("^" bitand)*
+*/
+fn bitxor(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Node {
+    let mut node = bitand(token, lvar, gvar);
+
+    while consume("^", token) {
+        node = new_node(
+            NodeKind::NdBitXor,
+            Some(Box::new(node)),
+            Some(Box::new(bitand(token, lvar, gvar))),
+        );
+    }
+    node
+}
+
+/*
+bitand = equality ("&" equality)*
+*/
+fn bitand(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Node {
+    let mut node = equality(token, lvar, gvar);
+
+    while consume("&", token) {
+        node = new_node(
+            NodeKind::NdBitAnd,
+            Some(Box::new(node)),
+            Some(Box::new(equality(token, lvar, gvar))),
+        );
+    }
+    node
+}
+
 /*
 equality = relational ("==" relational | "!=" relational)*
 */
 fn equality(
     token: &mut Option<Box<tokenizer::Token>>,
-    lvar: &mut Option<Box<LVar>>,
+    lvar: &mut Scope,
     gvar: &mut Option<Box<LVar>>,
 ) -> Node {
     let mut node = relational(token, lvar, gvar);
@@ -304,38 +2007,67 @@ fn equality(
 }
 
 /*
-relational = add ("<" add | "<=" add | ">" add | ">=" add)*
+relational = shift ("<" shift | "<=" shift | ">" shift | ">=" shift)*
 */
 fn relational(
     token: &mut Option<Box<tokenizer::Token>>,
-    lvar: &mut Option<Box<LVar>>,
+    lvar: &mut Scope,
     gvar: &mut Option<Box<LVar>>,
 ) -> Node {
-    let mut node = add(token, lvar, gvar);
+    let mut node = shift(token, lvar, gvar);
 
     loop {
         if consume("<", &mut token.borrow_mut()) {
             node = new_node(
                 NodeKind::NdLt,
                 Some(Box::new(node)),
-                Some(Box::new(add(token, lvar, gvar))),
+                Some(Box::new(shift(token, lvar, gvar))),
             );
         } else if consume("<=", &mut token.borrow_mut()) {
             node = new_node(
                 NodeKind::NdLe,
                 Some(Box::new(node)),
-                Some(Box::new(add(token, lvar, gvar))),
+                Some(Box::new(shift(token, lvar, gvar))),
             );
         } else if consume(">", &mut token.borrow_mut()) {
             node = new_node(
                 NodeKind::NdGt,
                 Some(Box::new(node)),
-                Some(Box::new(add(token, lvar, gvar))),
+                Some(Box::new(shift(token, lvar, gvar))),
             );
         } else if consume(">=", &mut token.borrow_mut()) {
             node = new_node(
                 NodeKind::NdGe,
                 Some(Box::new(node)),
+                Some(Box::new(shift(token, lvar, gvar))),
+            );
+        } else {
+            return node;
+        }
+    }
+}
+
+/*
+shift = add ("<<" add | ">>" add)*
+*/
+fn shift(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Node {
+    let mut node = add(token, lvar, gvar);
+
+    loop {
+        if consume("<<", token) {
+            node = new_node(
+                NodeKind::NdShl,
+                Some(Box::new(node)),
+                Some(Box::new(add(token, lvar, gvar))),
+            );
+        } else if consume(">>", token) {
+            node = new_node(
+                NodeKind::NdShr,
+                Some(Box::new(node)),
                 Some(Box::new(add(token, lvar, gvar))),
             );
         } else {
@@ -349,7 +2081,7 @@ add = mul ("+" mul | "-" mul)*
 */
 fn add(
     token: &mut Option<Box<tokenizer::Token>>,
-    lvar: &mut Option<Box<LVar>>,
+    lvar: &mut Scope,
     gvar: &mut Option<Box<LVar>>,
 ) -> Node {
     let mut node = mul(token, lvar, gvar);
@@ -376,27 +2108,33 @@ fn add(
 }
 
 /*
-mul = unary ("*" unary | "/" unary)*
+mul = cast ("*" cast | "/" cast | "%" cast)*
 */
 fn mul(
     token: &mut Option<Box<tokenizer::Token>>,
-    lvar: &mut Option<Box<LVar>>,
+    lvar: &mut Scope,
     gvar: &mut Option<Box<LVar>>,
 ) -> Node {
-    let mut node = unary(token, lvar, gvar);
+    let mut node = cast(token, lvar, gvar);
 
     loop {
         if consume("*", &mut token.borrow_mut()) {
             node = new_node(
                 NodeKind::NdMul,
                 Some(Box::new(node)),
-                Some(Box::new(unary(token, lvar, gvar))),
+                Some(Box::new(cast(token, lvar, gvar))),
             );
         } else if consume("/", &mut token.borrow_mut()) {
             node = new_node(
                 NodeKind::NdDiv,
                 Some(Box::new(node)),
-                Some(Box::new(unary(token, lvar, gvar))),
+                Some(Box::new(cast(token, lvar, gvar))),
+            );
+        } else if consume("%", token) {
+            node = new_node(
+                NodeKind::NdMod,
+                Some(Box::new(node)),
+                Some(Box::new(cast(token, lvar, gvar))),
             );
         } else {
             return node;
@@ -405,37 +2143,98 @@ fn mul(
 }
 
 /*
-unary = "sizeof" unary | ("+" | "-")? primary | "*" unary | "&" unary
+cast = "(" typename ")" cast | unary
+*/
+fn cast(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Node {
+    if !is_paren_typename(token, lvar) {
+        return unary(token, lvar, gvar);
+    }
+    expect("(", token);
+    let loc = token.as_ref().unwrap().loc;
+    let ty = typename(token, lvar, gvar);
+    expect(")", token);
+    let target = ty.as_ref().unwrap();
+    if is_float(target) {
+        error_at(loc, "floating-point types are not supported");
+    }
+    if matches!(
+        target.ty,
+        TypeKind::TyArray | TypeKind::TyFunc | TypeKind::TyStruct | TypeKind::TyUnion
+    ) {
+        error_at(loc, "cast to a non-scalar type");
+    }
+    new_node_cast(cast(token, lvar, gvar), ty)
+}
+
+/*
+typename = declspec declarator
+*/
+// a type without a name, as in a cast or sizeof
+fn typename(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Option<Box<Type>> {
+    let base = typespec(token, lvar, gvar);
+    let loc = token.as_ref().unwrap().loc;
+    let (name, ty) = declarator(token, base, lvar, gvar);
+    if !name.is_empty() {
+        error_at(loc, "unexpected identifier in type name");
+    }
+    ty
+}
+
+// true if the next tokens are "(" typename
+fn is_paren_typename(token: &Option<Box<tokenizer::Token>>, lvar: &Scope) -> bool {
+    check("(", token) && is_typename(&token.as_ref().unwrap().next, lvar)
+}
+
+/*
+unary = "sizeof" "(" typename ")"
+      | "sizeof" unary
+      | ("_Alignof" | "alignof") "(" typename ")"
+      | ("_Alignof" | "alignof") unary
+      | ("+" | "-") cast
+      | "*" cast
+      | "&" cast
+      | "!" cast
+      | "~" cast
+      | "&&" ident
+      | ("++" | "--") unary
+      | postfix
 */
 fn unary(
     token: &mut Option<Box<tokenizer::Token>>,
-    lvar: &mut Option<Box<LVar>>,
+    lvar: &mut Scope,
     gvar: &mut Option<Box<LVar>>,
 ) -> Node {
     if consume("+", &mut token.borrow_mut()) {
-        return primary(token, lvar, gvar);
+        return cast(token, lvar, gvar);
     }
     if consume("-", &mut token.borrow_mut()) {
         return new_node(
             NodeKind::NdNeg,
             Some(Box::new(new_node_num(0))),
-            Some(Box::new(primary(token, lvar, gvar))),
+            Some(Box::new(cast(token, lvar, gvar))),
         );
     }
     if consume_kind(tokenizer::TokenKind::TkSizeof, token) {
-        let mut node = unary(token, lvar, gvar);
-        add_type(&mut node);
-        if let Some(ty) = node.var_type {
-            return new_node_num(ty.size as i32);
-        } else {
-            error("no type");
-        }
+        let ty = sizeof_operand("sizeof", token, lvar, gvar);
+        return new_node_num(ty.size() as i64);
+    }
+    if consume_kind(tokenizer::TokenKind::TkAlignof, token) {
+        let ty = sizeof_operand("_Alignof", token, lvar, gvar);
+        return new_node_num(ty.align() as i64);
     }
     if consume("*", &mut token.borrow_mut()) {
         let mut new_deref_node = new_node(
             NodeKind::NdDeref,
             None,
-            Some(Box::new(unary(token, lvar, gvar))),
+            Some(Box::new(cast(token, lvar, gvar))),
         );
         add_type(&mut new_deref_node);
         return new_deref_node;
@@ -444,18 +2243,223 @@ fn unary(
         return new_node(
             NodeKind::NdAddr,
             None,
-            Some(Box::new(unary(token, lvar, gvar))),
+            Some(Box::new(cast(token, lvar, gvar))),
+        );
+    }
+    if consume("!", token) {
+        return new_node(
+            NodeKind::NdNot,
+            None,
+            Some(Box::new(cast(token, lvar, gvar))),
+        );
+    }
+    // GNU extension: the address of a label, for use with goto *
+    if consume("&&", token) {
+        let loc = token.as_ref().unwrap().loc;
+        let mut node = new_node(NodeKind::NdLabelVal, None, None);
+        node.label = goto_label(&expect_ident(token), loc);
+        return node;
+    }
+    if consume("~", token) {
+        return new_node(
+            NodeKind::NdBitNot,
+            None,
+            Some(Box::new(cast(token, lvar, gvar))),
+        );
+    }
+    // ++x is x += 1
+    let loc = token.as_ref().unwrap().loc;
+    if consume("++", token) {
+        let node = unary(token, lvar, gvar);
+        check_assignable(&node, loc);
+        return new_node_assign_op(NodeKind::NdAdd, node, new_node_num(1));
+    }
+    if consume("--", token) {
+        let node = unary(token, lvar, gvar);
+        check_assignable(&node, loc);
+        return new_node_assign_op(NodeKind::NdSub, node, new_node_num(1));
+    }
+    postfix(token, lvar, gvar)
+}
+
+// the type of the operand of sizeof or _Alignof, named op, which is a type
+// name in parentheses or an expression; void has size 1 as in GNU C, but
+// any other incomplete type has no size or alignment yet
+fn sizeof_operand(
+    op: &str,
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Box<Type> {
+    let loc = token.as_ref().unwrap().loc;
+    let ty = if is_paren_typename(token, lvar) {
+        expect("(", token);
+        let ty = typename(token, lvar, gvar);
+        expect(")", token);
+        ty.unwrap()
+    } else {
+        let mut node = unary(token, lvar, gvar);
+        add_type(&mut node);
+        match node.var_type {
+            Some(ty) => ty,
+            None => error("no type"),
+        }
+    };
+    if ty.ty != TypeKind::TyVoid && is_incomplete(&ty) {
+        error_at(
+            loc,
+            &format!("invalid application of '{}' to an incomplete type", op),
+        );
+    }
+    ty
+}
+
+/*
+postfix = primary ("(" func_args | "[" expr "]" | "." ident | "->" ident | "++" | "--")*
+*/
+fn postfix(
+    token: &mut Option<Box<tokenizer::Token>>,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Node {
+    let mut node = primary(token, lvar, gvar);
+
+    loop {
+        let loc = token.as_ref().unwrap().loc;
+        if check("++", token) || check("--", token) {
+            check_assignable(&node, loc);
+        }
+        if consume("(", token) {
+            node = func_call(token, node, loc, lvar, gvar);
+        } else if consume("[", token) {
+            let index = expr(token, lvar, gvar);
+            expect("]", token);
+            let add_node = new_node(NodeKind::NdAdd, Some(Box::new(node)), Some(Box::new(index)));
+            node = new_node(NodeKind::NdDeref, None, Some(Box::new(add_node)));
+        } else if consume(".", token) {
+            node = struct_member(token, node);
+        } else if consume("->", token) {
+            // x->y is (*x).y
+            let mut deref = new_node(NodeKind::NdDeref, None, Some(Box::new(node)));
+            add_type(&mut deref);
+            node = struct_member(token, deref);
+        } else if consume("++", token) {
+            node = new_node_post_op(NodeKind::NdAdd, node);
+        } else if consume("--", token) {
+            node = new_node_post_op(NodeKind::NdSub, node);
+        } else {
+            return node;
+        }
+    }
+}
+
+/*
+func_args = (assign ("," assign)*)? ")"
+*/
+// a call of callee, which is a function or a pointer to one; a function
+// named by a global is called by its symbol, anything else through the
+// address it evaluates to
+fn func_call(
+    token: &mut Option<Box<tokenizer::Token>>,
+    mut callee: Node,
+    loc: usize,
+    lvar: &mut Scope,
+    gvar: &mut Option<Box<LVar>>,
+) -> Node {
+    add_type(&mut callee);
+    let ty = callee.var_type.clone().unwrap();
+    let func_ty = match ty.ty {
+        TypeKind::TyFunc => ty,
+        TypeKind::TyPtr if ty.ptr_to.as_ref().unwrap().ty == TypeKind::TyFunc => ty.ptr_to.unwrap(),
+        _ => error_at(loc, "called object is not a function or function pointer"),
+    };
+    let mut args = Vec::new();
+    let mut locs = Vec::new();
+    if !consume(")", token) {
+        loop {
+            locs.push(token.as_ref().unwrap().loc);
+            args.push(assign(token, lvar, gvar));
+            if !consume(",", token) {
+                break;
+            }
+        }
+        expect(")", token);
+    }
+    for (i, ((_, param), arg)) in func_ty.params.iter().zip(&args).enumerate() {
+        let what = match callee.name.as_str() {
+            "" => format!("passing argument {}", i + 1),
+            name => format!("passing argument {} of '{}'", i + 1, name),
+        };
+        check_discarded_const(param, arg, locs[i], &what);
+    }
+    let is_direct = matches!(callee.kind, NodeKind::NdGvar)
+        && callee.var_type.as_ref().unwrap().ty == TypeKind::TyFunc;
+    let name = if is_direct {
+        callee.name.clone()
+    } else {
+        String::new()
+    };
+    let mut node = new_node_func(name, args, lvar, gvar);
+    node.var_type = func_ty.ptr_to;
+    if !is_direct {
+        node.lhs = Some(Box::new(callee));
+    }
+    if is_float(node.var_type.as_ref().unwrap()) {
+        error_at(loc, "floating-point types are not supported");
+    }
+    node
+}
+
+// the member named by the next token of the struct or union lhs
+fn struct_member(token: &mut Option<Box<tokenizer::Token>>, mut lhs: Node) -> Node {
+    let loc = token.as_ref().unwrap().loc;
+    let name = expect_ident(token);
+    add_type(&mut lhs);
+    let ty = lhs.var_type.clone().unwrap();
+    if !matches!(ty.ty, TypeKind::TyStruct | TypeKind::TyUnion) {
+        error_at(
+            loc,
+            "member reference base type is not a structure or union",
         );
     }
-    primary(token, lvar, gvar)
+    let members = match ty.members() {
+        Some(members) => members,
+        None => error_at(loc, "member access into incomplete type"),
+    };
+    match find_member(&members, &name) {
+        Some(member) => new_node_member(lhs, &member),
+        None => error_at(loc, &format!("no member named '{}'", name)),
+    }
+}
+
+// the member called name, looking into anonymous members, whose members
+// are offset by where they are
+fn find_member(members: &[Member], name: &str) -> Option<Member> {
+    for member in members {
+        if member.name == name {
+            return Some(member.clone());
+        }
+        if member.name.is_empty() {
+            let inner = member.ty.members().unwrap_or_default();
+            if let Some(mut found) = find_member(&inner, name) {
+                found.offset += member.offset;
+                return Some(found);
+            }
+        }
+    }
+    None
 }
 
 /*
-primary = num | ident | ident "(" expr ")" | ident "[" expr "]"
+primary = num
+        | str+
+        | ident
+        | ident "[" expr "]"
+        | "(" expr ")"
 */
 fn primary(
     token: &mut Option<Box<tokenizer::Token>>,
-    lvar: &mut Option<Box<LVar>>,
+    lvar: &mut Scope,
     gvar: &mut Option<Box<LVar>>,
 ) -> Node {
     if consume("(", &mut token.borrow_mut()) {
@@ -466,24 +2470,26 @@ fn primary(
 
     if let Some(current) = token {
         if let tokenizer::TokenKind::TkNum = current.kind {
-            return new_node_num(expect_number(&mut token.borrow_mut()));
+            let ty = current.ty.clone();
+            let mut node = new_node_num(expect_number(&mut token.borrow_mut()));
+            node.var_type = ty;
+            return node;
+        } else if let tokenizer::TokenKind::TkStr = current.kind {
+            return string_literal(token, gvar);
         } else if let tokenizer::TokenKind::TkIdent = current.kind {
+            let loc = current.loc;
             let ident = expect_ident(&mut token.borrow_mut());
 
-            if consume("(", &mut token.borrow_mut()) {
-                let mut args = Vec::new();
-                if !consume(")", &mut token.borrow_mut()) {
-                    args.push(expr(token, lvar, gvar));
-                    while consume(",", &mut token.borrow_mut()) {
-                        args.push(expr(token, lvar, gvar));
-                    }
-                    expect(")", &mut token.borrow_mut());
-                }
-                return new_node_func(ident, args);
-            } else if consume("[", &mut token.borrow_mut()) {
+            // a call of a function that was not declared, which returns int
+            if check("(", token) && lvar.find(&ident).is_none() && find_lvar(gvar, &ident).is_none()
+            {
+                let ty = new_type_func(new_type_int(), Vec::new(), false);
+                return new_node_gvar(ident, *ty.unwrap());
+            }
+            if consume("[", &mut token.borrow_mut()) {
                 let node = expr(token, lvar, gvar);
                 expect("]", &mut token.borrow_mut());
-                let array_node = new_node_lvar(ident, lvar, gvar);
+                let array_node = new_node_lvar(ident, loc, lvar, gvar);
                 let add_node = new_node(
                     NodeKind::NdAdd,
                     Some(Box::new(array_node)),
@@ -492,10 +2498,36 @@ fn primary(
                 return new_node(NodeKind::NdDeref, None, Some(Box::new(add_node)));
             }
 
-            return new_node_lvar(ident, lvar, gvar);
+            return new_node_lvar(ident, loc, lvar, gvar);
         }
         error("expected number or ident");
     } else {
         error("unexpected error");
     }
 }
+
+// the contents of adjacent string literals joined together with a
+// terminating nul
+fn string_contents_joined(token: &mut Option<Box<tokenizer::Token>>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    while check_kind(tokenizer::TokenKind::TkStr, token) {
+        bytes.extend(expect_string(token));
+    }
+    bytes.push(0);
+    bytes
+}
+
+// a string literal is an unnamed static array of char
+fn string_literal(token: &mut Option<Box<tokenizer::Token>>, gvar: &mut Option<Box<LVar>>) -> Node {
+    let bytes = string_contents_joined(token);
+    let label = format!(".L.str.{}", gen_label());
+    let ty = new_type_array(new_type_char(), bytes.len());
+    new_node_gvar_def(label.clone(), ty.clone(), gvar);
+    let string = gvar.as_mut().unwrap();
+    string.is_static = true;
+    string.init = bytes
+        .iter()
+        .map(|&byte| InitData::Int(1, byte as i8 as i64))
+        .collect();
+    new_node_gvar(label, *ty.unwrap())
+}
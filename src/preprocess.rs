@@ -0,0 +1,1108 @@
+use lazy_static::lazy_static;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::parser::const_expr;
+use crate::sema::{new_type_int, new_type_long, new_type_unsigned, Type};
+use crate::tokenizer::{invalid_literal, link_tokens, tokenizer, Token, TokenKind};
+use crate::util::{
+    add_line_directive, add_source_file, error_at, presumed_location, warn_at, SOURCE_FILES,
+};
+
+// searched by #include after the directories given with -I; the first holds
+// the headers that come with the compiler rather than the C library
+const SYSTEM_INCLUDE_PATHS: &[&str] = &[
+    concat!(env!("CARGO_MANIFEST_DIR"), "/include"),
+    "/usr/local/include",
+    "/usr/include/x86_64-linux-gnu",
+    "/usr/include",
+];
+
+// how deeply #include may nest before it is taken to be recursive
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+lazy_static! {
+    // directories given with -I, in command line order
+    pub static ref INCLUDE_PATHS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+    // files that asked with #pragma once never to be included again
+    static ref PRAGMA_ONCE: RwLock<Vec<PathBuf>> = RwLock::new(Vec::new());
+    // files wrapped in an include guard, with the guard's name
+    static ref INCLUDE_GUARDS: RwLock<Vec<(PathBuf, String)>> = RwLock::new(Vec::new());
+    static ref MACROS: RwLock<HashMap<String, Arc<Macro>>> = RwLock::new(HashMap::new());
+    // the next value of __COUNTER__
+    static ref COUNTER: RwLock<i64> = RwLock::new(0);
+}
+
+// an #if, #ifdef or #ifndef whose #endif has not been seen yet
+struct Cond {
+    loc: usize,
+    in_else: bool,
+    // whether one of its branches has been taken, after which the rest
+    // are skipped
+    included: bool,
+}
+
+struct Macro {
+    name: String,
+    // None for an object-like macro
+    params: Option<Vec<String>>,
+    // the name the variable arguments go by, for a variadic macro
+    va_args: Option<String>,
+    body: Vec<Token>,
+    // for a macro like __LINE__ whose value depends on where it is used,
+    // the token it expands to in place of the one naming it
+    handler: Option<fn(&Token) -> Token>,
+}
+
+// define the macros that exist before any source is read; -U may still
+// remove them
+pub fn init_macros() {
+    let (date, time) = date_and_time();
+    for def in [
+        "__STDC__ 1",
+        "__STDC_VERSION__ 201112L",
+        "__STDC_HOSTED__ 1",
+        "__x86_64__ 1",
+        "__x86_64 1",
+        "__linux__ 1",
+        "__linux 1",
+        "__unix__ 1",
+        "__unix 1",
+        "__LP64__ 1",
+        "_LP64 1",
+        "__ELF__ 1",
+        &format!("__DATE__ \"{}\"", date),
+        &format!("__TIME__ \"{}\"", time),
+    ] {
+        define_from_text("<built-in>", def);
+    }
+    add_handler("__FILE__", file_macro);
+    add_handler("__LINE__", line_macro);
+    add_handler("__COUNTER__", counter_macro);
+}
+
+fn add_handler(name: &str, handler: fn(&Token) -> Token) {
+    MACROS.write().unwrap().insert(
+        name.to_string(),
+        Arc::new(Macro {
+            name: name.to_string(),
+            params: None,
+            va_args: None,
+            body: Vec::new(),
+            handler: Some(handler),
+        }),
+    );
+}
+
+// where a token is said to be, which for one produced by a macro is where
+// the macro was used
+fn presumed_origin(tok: &Token) -> (String, usize) {
+    let (file, line) = tok.origin.unwrap_or((tok.file, tok.line));
+    presumed_location(file, line)
+}
+
+fn file_macro(tok: &Token) -> Token {
+    let name = presumed_origin(tok).0;
+    let mut file = tok.copy();
+    file.kind = TokenKind::TkStr;
+    file.str = quote(&name);
+    file
+}
+
+fn line_macro(tok: &Token) -> Token {
+    number_token(tok, presumed_origin(tok).1 as i64)
+}
+
+fn counter_macro(tok: &Token) -> Token {
+    let mut counter = COUNTER.write().unwrap();
+    *counter += 1;
+    number_token(tok, *counter - 1)
+}
+
+// __DATE__ and __TIME__ as in "Jan  1 1970" and "00:00:00", in UTC, for the
+// time given by SOURCE_DATE_EPOCH if it is set so builds can be reproduced
+fn date_and_time() -> (String, String) {
+    let secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs())
+        });
+    let days = (secs / 86400) as i64;
+    let secs = secs % 86400;
+
+    // the civil date of a day count, in a calendar whose years start in
+    // March so the leap day comes last
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    (
+        format!("{} {:>2} {}", MONTHS[month as usize - 1], day, year),
+        format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60),
+    )
+}
+
+// print preprocessed tokens as source text, laid out on the lines they came
+// from, with "# line" markers where the file changes or lines are skipped
+pub fn print_tokens(mut token: &Option<Box<Token>>) {
+    let mut out = String::new();
+    // file and line of the output line being written
+    let mut current: Option<(String, usize)> = None;
+    while let Some(tok) = token {
+        if tok.kind == TokenKind::TkEof {
+            break;
+        }
+        let (name, line) = presumed_origin(tok);
+        // a macro invocation spread over several lines is output on one
+        let starts_line = match &current {
+            Some((file, last)) => tok.at_bol && (*file != name || *last != line),
+            None => true,
+        };
+        if !starts_line {
+            if tok.has_space || tok.at_bol {
+                out.push(' ');
+            }
+        } else {
+            match &current {
+                Some((file, last)) if *file == name && *last < line && line <= *last + 8 => {
+                    out.push_str(&"\n".repeat(line - last));
+                }
+                _ => {
+                    if current.is_some() {
+                        out.push('\n');
+                    }
+                    out.push_str(&format!("# {} {}\n", line, quote(&name)));
+                }
+            }
+            current = Some((name, line));
+        }
+        out.push_str(&tok.str);
+        token = &tok.next;
+    }
+    if current.is_some() {
+        out.push('\n');
+    }
+    print!("{}", out);
+}
+
+// expand the preprocessing directives of a tokenized source file
+pub fn preprocess(token: Option<Box<Token>>) -> Option<Box<Token>> {
+    let mut output = Vec::new();
+    preprocess_file(unlink_tokens(token), &mut output, 0);
+    // an unclosed literal in a skipped group is no error, so it is only
+    // reported once it is known to be kept
+    if let Some(tok) = output.iter().find(|tok| tok.kind == TokenKind::TkInvalid) {
+        error_at(tok.loc, invalid_literal(tok));
+    }
+    link_tokens(output)
+}
+
+fn unlink_tokens(mut token: Option<Box<Token>>) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    while let Some(mut current) = token {
+        token = current.next.take();
+        tokens.push_back(*current);
+    }
+    tokens
+}
+
+// tokens is the contents of one file, ending with its TkEof, which is only
+// kept for the file being compiled
+fn preprocess_file(mut tokens: VecDeque<Token>, output: &mut Vec<Token>, depth: usize) {
+    let mut conds: Vec<Cond> = Vec::new();
+    while let Some(tok) = tokens.pop_front() {
+        if tok.kind == TokenKind::TkEof {
+            if let Some(cond) = conds.pop() {
+                error_at(cond.loc, "unterminated conditional directive");
+            }
+            if depth == 0 {
+                output.push(tok);
+            }
+            return;
+        }
+        if !(tok.at_bol && is_punct(&tok, "#")) {
+            if !expand_macro(&tok, &mut tokens) {
+                output.push(tok);
+            }
+            continue;
+        }
+        let line = directive_line(&mut tokens);
+        if !conditional_directive(&tok, &line, &mut conds, &mut tokens) {
+            directive(&tok, &line, output, depth);
+        }
+    }
+}
+
+fn is_punct(tok: &Token, op: &str) -> bool {
+    tok.kind == TokenKind::TkReserved && tok.str == op
+}
+
+fn is_ident(tok: &Token, name: &str) -> bool {
+    tok.kind == TokenKind::TkIdent && tok.str == name
+}
+
+// the rest of the line after the "#" of a directive
+fn directive_line(tokens: &mut VecDeque<Token>) -> Vec<Token> {
+    let mut line = Vec::new();
+    while tokens.front().is_some_and(|tok| !tok.at_bol) {
+        line.push(tokens.pop_front().unwrap());
+    }
+    line
+}
+
+fn directive(hash: &Token, line: &[Token], output: &mut Vec<Token>, depth: usize) {
+    // a "#" alone on a line does nothing
+    let name = match line.first() {
+        Some(name) => name,
+        None => return,
+    };
+    if is_ident(name, "include") {
+        include(hash, &line[1..], output, depth);
+    } else if is_ident(name, "define") {
+        define(hash, &line[1..]);
+    } else if is_ident(name, "undef") {
+        match &line[1..] {
+            [name] if name.kind == TokenKind::TkIdent => {
+                MACROS.write().unwrap().remove(&name.str);
+            }
+            [] => error_at(name.loc, "macro name missing"),
+            [name] => error_at(name.loc, "macro name must be an identifier"),
+            [_, extra, ..] => error_at(extra.loc, "extra tokens at end of #undef directive"),
+        }
+    } else if is_ident(name, "pragma") {
+        if line.len() == 2 && is_ident(&line[1], "once") {
+            let path = file_path(hash.file);
+            PRAGMA_ONCE.write().unwrap().push(canonical(&path));
+        }
+        // other pragmas are ignored
+    } else if is_ident(name, "error") {
+        error_at(name.loc, &format!("#error {}", spell(&line[1..])));
+    } else if is_ident(name, "warning") {
+        warn_at(name.loc, &format!("#warning {}", spell(&line[1..])));
+    } else if is_ident(name, "line") {
+        line_directive(hash, &line[1..]);
+    } else {
+        error_at(name.loc, "invalid preprocessing directive");
+    }
+}
+
+// handle hash line if it is a conditional directive, skipping the tokens
+// of branches not taken, and return whether it was one
+fn conditional_directive(
+    hash: &Token,
+    line: &[Token],
+    conds: &mut Vec<Cond>,
+    tokens: &mut VecDeque<Token>,
+) -> bool {
+    let name = match line.first() {
+        Some(name) if name.kind == TokenKind::TkIdent => name,
+        _ => return false,
+    };
+    let rest = &line[1..];
+    match name.str.as_str() {
+        "if" | "ifdef" | "ifndef" => {
+            let included = match name.str.as_str() {
+                "if" => eval_if(name, rest),
+                "ifdef" => is_defined(&macro_name(name, rest)),
+                _ => !is_defined(&macro_name(name, rest)),
+            };
+            conds.push(Cond {
+                loc: hash.loc,
+                in_else: false,
+                included,
+            });
+            if !included {
+                skip_cond_incl(tokens);
+            }
+        }
+        "elif" | "elifdef" | "elifndef" | "else" => {
+            let cond = match conds.last_mut() {
+                Some(cond) => cond,
+                None => error_at(name.loc, &format!("#{} without #if", name.str)),
+            };
+            if cond.in_else {
+                error_at(name.loc, &format!("#{} after #else", name.str));
+            }
+            if name.str == "else" {
+                cond.in_else = true;
+                if let Some(extra) = rest.first() {
+                    warn_at(extra.loc, "extra tokens at end of #else directive");
+                }
+            }
+            // the condition is not evaluated once a branch has been taken
+            let included = !cond.included
+                && match name.str.as_str() {
+                    "elif" => eval_if(name, rest),
+                    "elifdef" => is_defined(&macro_name(name, rest)),
+                    "elifndef" => !is_defined(&macro_name(name, rest)),
+                    _ => true,
+                };
+            if included {
+                cond.included = true;
+            } else {
+                skip_cond_incl(tokens);
+            }
+        }
+        "endif" => {
+            if conds.pop().is_none() {
+                error_at(name.loc, "#endif without #if");
+            }
+            if let Some(extra) = rest.first() {
+                warn_at(extra.loc, "extra tokens at end of #endif directive");
+            }
+        }
+        _ => return false,
+    }
+    true
+}
+
+// the identifier after #ifdef and the like
+fn macro_name(directive: &Token, rest: &[Token]) -> String {
+    match rest {
+        [] => error_at(directive.loc, "macro name missing"),
+        [name, ..] if name.kind != TokenKind::TkIdent => {
+            error_at(name.loc, "macro name must be an identifier")
+        }
+        [name, extra, ..] => {
+            warn_at(
+                extra.loc,
+                &format!("extra tokens at end of #{} directive", directive.str),
+            );
+            name.str.clone()
+        }
+        [name] => name.str.clone(),
+    }
+}
+
+// drop tokens up to the #elif, #else or #endif that ends the current
+// branch, skipping over nested conditionals
+fn skip_cond_incl(tokens: &mut VecDeque<Token>) {
+    let mut depth = 0;
+    while let Some(tok) = tokens.front() {
+        if tok.kind == TokenKind::TkEof {
+            return;
+        }
+        if let Some(name) = directive_name(tokens, 0) {
+            match name {
+                "if" | "ifdef" | "ifndef" => depth += 1,
+                "elif" | "elifdef" | "elifndef" | "else" if depth == 0 => return,
+                "endif" if depth == 0 => return,
+                "endif" => depth -= 1,
+                _ => {}
+            }
+        }
+        tokens.pop_front();
+    }
+}
+
+// the name of the directive starting at tokens[i], if one does
+fn directive_name(tokens: &VecDeque<Token>, i: usize) -> Option<&str> {
+    let hash = tokens.get(i)?;
+    let name = tokens.get(i + 1)?;
+    if hash.at_bol && is_punct(hash, "#") && !name.at_bol && name.kind == TokenKind::TkIdent {
+        return Some(&name.str);
+    }
+    None
+}
+
+/*
+if = constant expression, where "defined" ident and "defined" "(" ident ")"
+     are 1 if the macro is defined and 0 otherwise
+*/
+fn eval_if(directive: &Token, rest: &[Token]) -> bool {
+    let mut line = Vec::new();
+    let mut i = 0;
+    while i < rest.len() {
+        let tok = &rest[i];
+        if !is_ident(tok, "defined") {
+            line.push(tok.copy());
+            i += 1;
+            continue;
+        }
+        let paren = rest.get(i + 1).is_some_and(|tok| is_punct(tok, "("));
+        let name = match rest.get(i + 1 + paren as usize) {
+            Some(name) if name.kind == TokenKind::TkIdent => name,
+            _ => error_at(tok.loc, "macro name must be an identifier"),
+        };
+        i += 2 + paren as usize;
+        if paren {
+            match rest.get(i) {
+                Some(tok) if is_punct(tok, ")") => i += 1,
+                _ => error_at(name.loc, "missing ')' after 'defined'"),
+            }
+        }
+        line.push(number_token(tok, is_defined(&name.str) as i64));
+    }
+
+    let mut line = expand_all(line);
+    if line.is_empty() {
+        error_at(
+            directive.loc,
+            &format!("#{} with no expression", directive.str),
+        );
+    }
+    // identifiers left after expansion are not macros, and count as 0
+    for tok in line.iter_mut() {
+        if tok.kind == TokenKind::TkInvalid {
+            error_at(tok.loc, invalid_literal(tok));
+        }
+        if tok.kind == TokenKind::TkIdent {
+            *tok = number_token(tok, 0);
+        }
+        if tok.kind == TokenKind::TkNum {
+            tok.ty = intmax_type(tok);
+        }
+    }
+    let mut eof = directive.copy();
+    eof.kind = TokenKind::TkEof;
+    eof.loc = line.last().unwrap().loc;
+    line.push(eof);
+    const_expr(&mut link_tokens(line)) != 0
+}
+
+// #if computes in intmax_t, or uintmax_t for a number with a "u" suffix or
+// too large for intmax_t; they are long and unsigned long here
+fn intmax_type(num: &Token) -> Option<Box<Type>> {
+    let is_char = num.str.starts_with('\'');
+    let is_unsigned =
+        !is_char && (num.str.contains(['u', 'U']) || num.val.is_some_and(|val| val < 0));
+    if is_unsigned {
+        new_type_unsigned(new_type_long())
+    } else {
+        new_type_long()
+    }
+}
+
+fn number_token(tok: &Token, val: i64) -> Token {
+    let mut num = tok.copy();
+    num.kind = TokenKind::TkNum;
+    num.str = val.to_string();
+    num.val = Some(val);
+    num.ty = new_type_int();
+    num
+}
+
+/*
+line = number string?, after macro expansion
+*/
+fn line_directive(hash: &Token, rest: &[Token]) {
+    // the line the directive ends on, which a backslash may have continued
+    let line = rest.last().map_or(hash.line, |last| last.line);
+    let rest = expand_all(rest.iter().map(Token::copy).collect());
+    // the line number is a decimal digit sequence even with a leading 0,
+    // not any integer constant
+    let new_line = match rest.first() {
+        Some(tok) if tok.str.bytes().all(|c| c.is_ascii_digit()) => match tok.str.parse() {
+            Ok(line) => line,
+            Err(_) => error_at(tok.loc, "#line number out of range"),
+        },
+        Some(tok) => error_at(
+            tok.loc,
+            "#line directive requires a positive integer argument",
+        ),
+        None => error_at(
+            hash.loc,
+            "#line directive requires a positive integer argument",
+        ),
+    };
+    let name = match rest.get(1) {
+        Some(tok) if tok.kind == TokenKind::TkStr => {
+            Some(tok.str[1..tok.str.len() - 1].to_string())
+        }
+        Some(tok) => error_at(tok.loc, "invalid filename for #line directive"),
+        None => None,
+    };
+    if let Some(extra) = rest.get(2) {
+        warn_at(extra.loc, "extra tokens at end of #line directive");
+    }
+    add_line_directive(hash.file, line, new_line, name);
+}
+
+// the tokens as written, for a diagnostic
+fn spell(tokens: &[Token]) -> String {
+    let mut text = String::new();
+    for (i, tok) in tokens.iter().enumerate() {
+        if i > 0 && tok.has_space {
+            text.push(' ');
+        }
+        text.push_str(&tok.str);
+    }
+    text
+}
+
+/*
+include = "\"" path "\"" | "<" path ">" | tokens that expand to one of those
+*/
+fn include(hash: &Token, line: &[Token], output: &mut Vec<Token>, depth: usize) {
+    let expanded;
+    let line = match line.first() {
+        Some(tok) if tok.kind == TokenKind::TkIdent => {
+            expanded = expand_all(line.iter().map(Token::copy).collect());
+            &expanded[..]
+        }
+        _ => line,
+    };
+    let (name, quoted, len) = match line.first() {
+        Some(tok) if tok.kind == TokenKind::TkStr => {
+            (tok.str[1..tok.str.len() - 1].to_string(), true, 1)
+        }
+        Some(tok) if is_punct(tok, "<") => {
+            let end = match line.iter().position(|tok| is_punct(tok, ">")) {
+                Some(end) => end,
+                None => error_at(tok.loc, "expected '>'"),
+            };
+            let name: String = line[1..end].iter().map(|tok| tok.str.as_str()).collect();
+            (name, false, end + 1)
+        }
+        _ => error_at(hash.loc, "expected \"FILENAME\" or <FILENAME>"),
+    };
+    if let Some(extra) = line.get(len) {
+        error_at(extra.loc, "extra tokens at end of #include directive");
+    }
+    if depth >= MAX_INCLUDE_DEPTH {
+        error_at(hash.loc, "#include nested too deeply");
+    }
+
+    let path = match search_include(&name, quoted, hash.file) {
+        Some(path) => path,
+        None => error_at(line[0].loc, &format!("'{}' file not found", name)),
+    };
+    let real_path = canonical(&path);
+    if PRAGMA_ONCE.read().unwrap().contains(&real_path) {
+        return;
+    }
+    if INCLUDE_GUARDS
+        .read()
+        .unwrap()
+        .iter()
+        .any(|(guarded, guard)| *guarded == real_path && is_defined(guard))
+    {
+        return;
+    }
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(err) => error_at(line[0].loc, &format!("cannot open '{}': {}", name, err)),
+    };
+
+    let file = add_source_file(&path.to_string_lossy(), contents);
+    let tokens = unlink_tokens(tokenizer(file));
+    if let Some(guard) = include_guard(&tokens) {
+        // an inclusion while the guard is defined leaves nothing, so later
+        // ones skip the file without reading it
+        INCLUDE_GUARDS.write().unwrap().push((real_path, guard));
+    }
+    preprocess_file(tokens, output, depth + 1);
+}
+
+// the guard macro X of a file that is wholly wrapped in
+//   #ifndef X
+//   #define X
+//   ...
+//   #endif
+fn include_guard(tokens: &VecDeque<Token>) -> Option<String> {
+    let len = tokens.len();
+    if len < 9 {
+        return None;
+    }
+    let at = |i: usize| &tokens[i];
+    let is_directive = |i: usize, name: &str| {
+        at(i).at_bol && is_punct(at(i), "#") && is_ident(at(i + 1), name) && !at(i + 1).at_bol
+    };
+    let guard = &at(2).str;
+    if !(is_directive(0, "ifndef")
+        && at(2).kind == TokenKind::TkIdent
+        && !at(2).at_bol
+        && is_directive(3, "define")
+        && is_ident(at(5), guard)
+        && !at(5).at_bol
+        && at(6).at_bol
+        && is_directive(len - 3, "endif"))
+    {
+        return None;
+    }
+    // the #ifndef must be closed by the last #endif, not an earlier one
+    let mut depth = 0;
+    for i in 0..len - 3 {
+        match directive_name(tokens, i) {
+            Some("if" | "ifdef" | "ifndef") => depth += 1,
+            Some("endif") => {
+                depth -= 1;
+                if depth == 0 {
+                    return None;
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(guard.clone())
+}
+
+// where the file called name is, looking next to the including file first
+// for #include "..."
+fn search_include(name: &str, quoted: bool, includer: usize) -> Option<PathBuf> {
+    if name.starts_with('/') {
+        return Some(PathBuf::from(name)).filter(|path| path.is_file());
+    }
+    let mut dirs = Vec::new();
+    if quoted {
+        let includer = file_path(includer);
+        dirs.push(includer.parent().unwrap_or(Path::new("")).to_path_buf());
+    }
+    for dir in INCLUDE_PATHS.read().unwrap().iter() {
+        dirs.push(PathBuf::from(dir));
+    }
+    for dir in SYSTEM_INCLUDE_PATHS {
+        dirs.push(PathBuf::from(dir));
+    }
+    dirs.into_iter()
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+fn file_path(file: usize) -> PathBuf {
+    PathBuf::from(&SOURCE_FILES.read().unwrap()[file].name)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn is_defined(name: &str) -> bool {
+    MACROS.read().unwrap().contains_key(name)
+}
+
+/*
+define = ident body
+       | ident "(" (params ("," "...")? | "...")? ")" body
+params = ident ("," ident)* | ident "..."
+*/
+fn define(hash: &Token, line: &[Token]) {
+    let name = match line.first() {
+        Some(name) if name.kind == TokenKind::TkIdent => name,
+        Some(name) => error_at(name.loc, "macro name must be an identifier"),
+        None => error_at(hash.loc, "macro name missing"),
+    };
+    let mut rest = &line[1..];
+    let mut params = None;
+    let mut va_args = None;
+    // a function-like macro has "(" right after its name
+    if rest
+        .first()
+        .is_some_and(|tok| is_punct(tok, "(") && !tok.has_space)
+    {
+        let mut names = Vec::new();
+        let mut i = 1;
+        if rest.get(i).is_some_and(|tok| is_punct(tok, ")")) {
+            i += 1;
+        } else {
+            loop {
+                let tok = match rest.get(i) {
+                    Some(tok) => tok,
+                    None => error_at(name.loc, "missing ')' in macro parameter list"),
+                };
+                if is_punct(tok, "...") {
+                    va_args = Some("__VA_ARGS__".to_string());
+                    i += 1;
+                } else if tok.kind == TokenKind::TkIdent {
+                    if names.contains(&tok.str) {
+                        error_at(tok.loc, "duplicate macro parameter name");
+                    }
+                    // GNU extension: a named variadic parameter
+                    if rest.get(i + 1).is_some_and(|tok| is_punct(tok, "...")) {
+                        va_args = Some(tok.str.clone());
+                        i += 2;
+                    } else {
+                        names.push(tok.str.clone());
+                        i += 1;
+                    }
+                } else {
+                    error_at(tok.loc, "expected parameter name");
+                }
+                match rest.get(i) {
+                    Some(tok) if is_punct(tok, ")") => {
+                        i += 1;
+                        break;
+                    }
+                    Some(tok) if is_punct(tok, ",") && va_args.is_none() => i += 1,
+                    Some(tok) => error_at(tok.loc, "expected ')' in macro parameter list"),
+                    None => error_at(name.loc, "missing ')' in macro parameter list"),
+                }
+            }
+        }
+        params = Some(names);
+        rest = &rest[i..];
+    }
+
+    let body: Vec<Token> = rest.iter().map(Token::copy).collect();
+    for (i, tok) in body.iter().enumerate() {
+        if is_punct(tok, "##") && (i == 0 || i == body.len() - 1) {
+            error_at(
+                tok.loc,
+                "'##' cannot appear at either end of a macro expansion",
+            );
+        }
+    }
+    if params.is_some() {
+        let is_param = |tok: &Token| param_index(params.as_ref().unwrap(), &va_args, tok).is_some();
+        for (i, tok) in body.iter().enumerate() {
+            if is_punct(tok, "#") && !body.get(i + 1).is_some_and(is_param) {
+                error_at(tok.loc, "'#' is not followed by a macro parameter");
+            }
+        }
+    }
+    let name = name.str.clone();
+    MACROS.write().unwrap().insert(
+        name.clone(),
+        Arc::new(Macro {
+            name,
+            params,
+            va_args,
+            body,
+            handler: None,
+        }),
+    );
+}
+
+// define a macro given as -D name or -D name=value
+pub fn define_from_command_line(arg: &str) {
+    let text = match arg.split_once('=') {
+        Some((name, value)) => format!("{} {}", name, value),
+        None => format!("{} 1", arg),
+    };
+    define_from_text("<command line>", &text);
+}
+
+// define a macro from the text of a #define line after "#define"
+fn define_from_text(file_name: &str, text: &str) {
+    let file = add_source_file(file_name, text.to_string());
+    let mut line = unlink_tokens(tokenizer(file));
+    let hash = line.pop_back().unwrap();
+    define(&hash, line.make_contiguous());
+}
+
+pub fn undef_from_command_line(name: &str) {
+    MACROS.write().unwrap().remove(name);
+}
+
+// the position of the macro parameter tok names, with the variable
+// arguments coming after all named parameters
+fn param_index(params: &[String], va_args: &Option<String>, tok: &Token) -> Option<usize> {
+    if tok.kind != TokenKind::TkIdent {
+        return None;
+    }
+    if let Some(i) = params.iter().position(|param| *param == tok.str) {
+        return Some(i);
+    }
+    if va_args.as_deref() == Some(tok.str.as_str()) {
+        return Some(params.len());
+    }
+    None
+}
+
+// if tok names a macro, replace it by the macro's expansion at the front of
+// tokens and return true
+fn expand_macro(tok: &Token, tokens: &mut VecDeque<Token>) -> bool {
+    if tok.kind != TokenKind::TkIdent || tok.hideset.contains(&tok.str) {
+        return false;
+    }
+    let mac = match MACROS.read().unwrap().get(&tok.str) {
+        Some(mac) => mac.clone(),
+        None => return false,
+    };
+    let origin = tok.origin.or(Some((tok.file, tok.line)));
+    if let Some(handler) = mac.handler {
+        let mut expanded = handler(tok);
+        expanded.origin = origin;
+        tokens.push_front(expanded);
+        return true;
+    }
+
+    let (mut expansion, hideset) = match &mac.params {
+        None => (
+            subst(&mac, &[], &mac.body, &[]),
+            add_hideset(&tok.hideset, &mac.name),
+        ),
+        Some(params) => {
+            // a function-like macro name without arguments is left alone
+            if !tokens.front().is_some_and(|next| is_punct(next, "(")) {
+                return false;
+            }
+            let (args, rparen) = read_macro_args(tok, &mac, params, tokens);
+            // the expansion may not expand the macro again, but tokens
+            // following the invocation still may
+            let hideset: Vec<String> = tok
+                .hideset
+                .iter()
+                .filter(|name| rparen.hideset.contains(name))
+                .cloned()
+                .collect();
+            (
+                subst(&mac, params, &mac.body, &args),
+                add_hideset(&hideset, &mac.name),
+            )
+        }
+    };
+
+    for expanded in expansion.iter_mut() {
+        expanded.origin = origin;
+        for name in &hideset {
+            if !expanded.hideset.contains(name) {
+                expanded.hideset.push(name.clone());
+            }
+        }
+    }
+    if let Some(first) = expansion.first_mut() {
+        first.at_bol = tok.at_bol;
+        first.has_space = tok.has_space;
+    }
+    for expanded in expansion.into_iter().rev() {
+        tokens.push_front(expanded);
+    }
+    true
+}
+
+fn add_hideset(hideset: &[String], name: &str) -> Vec<String> {
+    let mut hideset = hideset.to_vec();
+    hideset.push(name.to_string());
+    hideset
+}
+
+// the arguments of a function-like macro invocation, which start at the
+// "(" in front of tokens, and the ")" that ends them
+fn read_macro_args(
+    name: &Token,
+    mac: &Macro,
+    params: &[String],
+    tokens: &mut VecDeque<Token>,
+) -> (Vec<Vec<Token>>, Token) {
+    tokens.pop_front();
+    let mut args = vec![Vec::new()];
+    let mut depth = 0;
+    loop {
+        let tok = match tokens.pop_front() {
+            Some(tok) if tok.kind != TokenKind::TkEof => tok,
+            _ => error_at(
+                name.loc,
+                &format!("unterminated argument list invoking macro '{}'", mac.name),
+            ),
+        };
+        if depth == 0 && is_punct(&tok, ")") {
+            let arity = params.len() + mac.va_args.is_some() as usize;
+            // "F()" passes one empty argument, or none if F takes none
+            if arity == 0 && args.len() == 1 && args[0].is_empty() {
+                args.clear();
+            }
+            // the variable arguments may be left out altogether
+            if mac.va_args.is_some() && args.len() == params.len() {
+                args.push(Vec::new());
+            }
+            if args.len() > arity {
+                error_at(
+                    name.loc,
+                    &format!("too many arguments provided to macro '{}'", mac.name),
+                );
+            }
+            if args.len() < arity {
+                error_at(
+                    name.loc,
+                    &format!("too few arguments provided to macro '{}'", mac.name),
+                );
+            }
+            return (args, tok);
+        }
+        if is_punct(&tok, "(") {
+            depth += 1;
+        } else if is_punct(&tok, ")") {
+            depth -= 1;
+        }
+        // commas separate arguments, except inside parentheses or among
+        // the variable arguments
+        if depth == 0
+            && is_punct(&tok, ",")
+            && !(mac.va_args.is_some() && args.len() > params.len())
+        {
+            args.push(Vec::new());
+            continue;
+        }
+        args.last_mut().unwrap().push(tok);
+    }
+}
+
+// the body of a macro with its parameters replaced and its ## operators
+// applied
+fn subst(mac: &Macro, params: &[String], body: &[Token], args: &[Vec<Token>]) -> Vec<Token> {
+    let param = |tok: &Token| param_index(params, &mac.va_args, tok);
+    let va_args_empty = args.last().is_some_and(|va| va.is_empty());
+    let mut out: Vec<Token> = Vec::new();
+    let mut i = 0;
+    while i < body.len() {
+        let tok = &body[i];
+        let next = body.get(i + 1);
+
+        // "#" param is the argument spelled as a string literal
+        if mac.params.is_some() && is_punct(tok, "#") {
+            let arg = &args[param(next.unwrap()).unwrap()];
+            out.push(stringize(tok, arg));
+            i += 2;
+            continue;
+        }
+
+        // GNU extension: in ", ## __VA_ARGS__" the comma disappears with
+        // empty variable arguments
+        if is_punct(tok, ",")
+            && next.is_some_and(|next| is_punct(next, "##"))
+            && mac.va_args.is_some()
+            && body
+                .get(i + 2)
+                .is_some_and(|va| param(va) == Some(params.len()))
+        {
+            if va_args_empty {
+                i += 3;
+            } else {
+                out.push(tok.copy());
+                i += 2;
+            }
+            continue;
+        }
+
+        if is_punct(tok, "##") {
+            let rhs = next.unwrap();
+            let rhs = match param(rhs) {
+                Some(index) => &args[index][..],
+                None => std::slice::from_ref(rhs),
+            };
+            if let Some((first, rest)) = rhs.split_first() {
+                // the left operand may have been an empty argument
+                match out.pop() {
+                    Some(lhs) => out.push(paste(&lhs, first)),
+                    None => out.push(first.copy()),
+                }
+                out.extend(rest.iter().map(Token::copy));
+            }
+            i += 2;
+            continue;
+        }
+
+        // __VA_OPT__(tokens) is the tokens only if there are variable arguments
+        if mac.va_args.is_some() && is_ident(tok, "__VA_OPT__") {
+            let end = match next {
+                Some(next) if is_punct(next, "(") => matching_paren(body, i + 1),
+                _ => error_at(tok.loc, "'__VA_OPT__' must be followed by '('"),
+            };
+            if !va_args_empty {
+                out.extend(subst(mac, params, &body[i + 2..end], args));
+            }
+            i = end + 1;
+            continue;
+        }
+
+        if let Some(index) = param(tok) {
+            let arg = &args[index];
+            if next.is_some_and(|next| is_punct(next, "##")) {
+                // an operand of ## is not macro expanded
+                if arg.is_empty() {
+                    // nothing to paste to, so the right operand stands alone
+                    let rhs = &body[i + 2];
+                    match param(rhs) {
+                        Some(index) => out.extend(args[index].iter().map(Token::copy)),
+                        None => out.push(rhs.copy()),
+                    }
+                    i += 3;
+                } else {
+                    out.extend(arg.iter().map(Token::copy));
+                    i += 1;
+                }
+                continue;
+            }
+            let mut expanded = expand_all(arg.iter().map(Token::copy).collect());
+            if let Some(first) = expanded.first_mut() {
+                first.has_space = tok.has_space;
+            }
+            out.extend(expanded);
+            i += 1;
+            continue;
+        }
+
+        out.push(tok.copy());
+        i += 1;
+    }
+    out
+}
+
+// the index of the ")" matching the "(" at body[open]
+fn matching_paren(body: &[Token], open: usize) -> usize {
+    let mut depth = 0;
+    for (i, tok) in body.iter().enumerate().skip(open) {
+        if is_punct(tok, "(") {
+            depth += 1;
+        } else if is_punct(tok, ")") {
+            depth -= 1;
+            if depth == 0 {
+                return i;
+            }
+        }
+    }
+    error_at(body[open].loc, "missing ')'");
+}
+
+// the tokens with every macro in them expanded
+fn expand_all(tokens: Vec<Token>) -> Vec<Token> {
+    let mut tokens = VecDeque::from(tokens);
+    let mut out = Vec::new();
+    while let Some(tok) = tokens.pop_front() {
+        if !expand_macro(&tok, &mut tokens) {
+            out.push(tok);
+        }
+    }
+    out
+}
+
+// the tokens of arg spelled as a string literal
+fn stringize(hash: &Token, arg: &[Token]) -> Token {
+    let mut tok = hash.copy();
+    tok.kind = TokenKind::TkStr;
+    tok.str = quote(&spell(arg));
+    tok
+}
+
+// text as a string literal
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+// the token spelled as lhs followed by rhs
+fn paste(lhs: &Token, rhs: &Token) -> Token {
+    let text = format!("{}{}", lhs.str, rhs.str);
+    let file = add_source_file("<scratch space>", text.clone());
+    let mut tokens = unlink_tokens(tokenizer(file));
+    // the text and TkEof
+    if tokens.len() != 2 {
+        error_at(
+            lhs.loc,
+            &format!("pasting formed '{}', an invalid preprocessing token", text),
+        );
+    }
+    let mut tok = tokens.pop_front().unwrap();
+    tok.loc = lhs.loc;
+    tok.file = lhs.file;
+    tok.line = lhs.line;
+    tok.at_bol = lhs.at_bol;
+    tok.has_space = lhs.has_space;
+    tok.hideset = lhs.hideset.clone();
+    tok
+}
@@ -1,42 +1,194 @@
+use lazy_static::lazy_static;
+use std::sync::RwLock;
+
 use crate::ast::{Node, NodeKind};
 
+lazy_static! {
+    // the layouts of the structs and unions declared so far
+    static ref LAYOUTS: RwLock<Vec<Layout>> = RwLock::new(Vec::new());
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum TypeKind {
+    TyVoid,
+    TyBool,
+    TyChar,
+    TyShort,
     TyInt,
+    TyLong,
+    // floating-point types can be declared but not yet computed with
+    TyFloat,
+    TyDouble,
+    TyLDouble,
     TyPtr,
     TyArray,
-}
-
-impl TypeKind {
-    pub fn size(&self) -> i32 {
-        match self {
-            TypeKind::TyInt => 4,
-            TypeKind::TyPtr => 8,
-            TypeKind::TyArray => 0,
-        }
-    }
+    TyFunc,
+    TyStruct,
+    TyUnion,
 }
 
 #[derive(Clone, Debug)]
 pub struct Type {
     pub ty: TypeKind,
     pub size: usize,
+    pub align: usize,
+    // what a pointer points to, the element of an array or the return type
+    // of a function
     pub ptr_to: Option<Box<Type>>,
     pub array_size: usize,
+    pub is_unsigned: bool,
+    pub is_const: bool,
+    // every access to a volatile object has side effects, so no pass may
+    // drop, merge or reorder one
+    pub is_volatile: bool,
+    pub is_restrict: bool,
+    // the parameters of a function, named "" where a declaration omits the
+    // name, and whether more arguments may follow them
+    pub params: Vec<(String, Type)>,
+    pub is_variadic: bool,
+    // for a struct or union, the index of its layout in LAYOUTS, which every
+    // copy of the type shares so that a definition completes them all
+    pub layout: Option<usize>,
+}
+
+// the size, alignment and members of a struct or union, which has no
+// members while it is incomplete
+struct Layout {
+    size: usize,
+    align: usize,
+    members: Option<Vec<Member>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Member {
+    pub name: String, // empty for an anonymous struct or union member
+    pub ty: Type,
+    pub offset: usize,
+}
+
+impl Type {
+    pub fn size(&self) -> usize {
+        match self.layout {
+            Some(id) => LAYOUTS.read().unwrap()[id].size,
+            None => self.size,
+        }
+    }
+
+    pub fn align(&self) -> usize {
+        match self.layout {
+            Some(id) => LAYOUTS.read().unwrap()[id].align,
+            None => self.align,
+        }
+    }
+
+    // the members of a struct or union, or None while it is incomplete
+    pub fn members(&self) -> Option<Vec<Member>> {
+        self.layout
+            .and_then(|id| LAYOUTS.read().unwrap()[id].members.clone())
+    }
 }
 
 pub fn new_type_int() -> Option<Box<Type>> {
-    return new_type(TypeKind::TyInt, 4, None, 0);
+    return new_type(TypeKind::TyInt, 4, 4, None, 0);
+}
+
+pub fn new_type_long() -> Option<Box<Type>> {
+    new_type(TypeKind::TyLong, 8, 8, None, 0)
+}
+
+pub fn new_type_char() -> Option<Box<Type>> {
+    new_type(TypeKind::TyChar, 1, 1, None, 0)
+}
+
+pub fn new_type_unsigned(ty: Option<Box<Type>>) -> Option<Box<Type>> {
+    let mut ty = ty;
+    ty.as_mut().unwrap().is_unsigned = true;
+    ty
+}
+
+pub fn new_type_func(
+    return_type: Option<Box<Type>>,
+    params: Vec<(String, Type)>,
+    is_variadic: bool,
+) -> Option<Box<Type>> {
+    // GNU extension: sizeof of a function is 1
+    let mut ty = new_type(TypeKind::TyFunc, 1, 1, return_type, 0);
+    let func = ty.as_mut().unwrap();
+    func.params = params;
+    func.is_variadic = is_variadic;
+    ty
+}
+
+pub fn is_integer(ty: &Type) -> bool {
+    matches!(
+        ty.ty,
+        TypeKind::TyBool
+            | TypeKind::TyChar
+            | TypeKind::TyShort
+            | TypeKind::TyInt
+            | TypeKind::TyLong
+    )
+}
+
+pub fn is_float(ty: &Type) -> bool {
+    matches!(
+        ty.ty,
+        TypeKind::TyFloat | TypeKind::TyDouble | TypeKind::TyLDouble
+    )
+}
+
+// the type integer operands are computed in: anything narrower than int
+// becomes int, and the wider or unsigned operand decides otherwise
+pub fn arith_type(lhs: &Type, rhs: &Type) -> Option<Box<Type>> {
+    let size = lhs.size().max(rhs.size());
+    let is_unsigned = [lhs, rhs]
+        .iter()
+        .any(|ty| ty.is_unsigned && ty.size() == size.max(4));
+    let ty = if size == 8 {
+        new_type_long()
+    } else {
+        new_type_int()
+    };
+    if is_unsigned {
+        new_type_unsigned(ty)
+    } else {
+        ty
+    }
+}
+
+// a struct or union that is incomplete until define_struct gives it members
+pub fn new_type_struct(kind: TypeKind) -> Option<Box<Type>> {
+    let mut layouts = LAYOUTS.write().unwrap();
+    let mut ty = new_type(kind, 0, 1, None, 0);
+    ty.as_mut().unwrap().layout = Some(layouts.len());
+    layouts.push(Layout {
+        size: 0,
+        align: 1,
+        members: None,
+    });
+    ty
+}
+
+// complete the struct or union ty, and with it every type that refers to it
+pub fn define_struct(ty: &Type, size: usize, align: usize, members: Vec<Member>) {
+    let id = ty.layout.unwrap();
+    LAYOUTS.write().unwrap()[id] = Layout {
+        size,
+        align,
+        members: Some(members),
+    };
 }
 
 pub fn new_type_ptr(node_type: Option<Box<Type>>) -> Option<Box<Type>> {
-    return new_type(TypeKind::TyPtr, 8, node_type, 0);
+    return new_type(TypeKind::TyPtr, 8, 8, node_type, 0);
 }
 
 pub fn new_type_array(node_type: Option<Box<Type>>, size: usize) -> Option<Box<Type>> {
+    // an array is aligned like its element
     return new_type(
         TypeKind::TyArray,
-        node_type.as_ref().unwrap().size * size,
+        node_type.as_ref().unwrap().size() * size,
+        node_type.as_ref().unwrap().align(),
         node_type,
         size,
     );
@@ -45,17 +197,71 @@ pub fn new_type_array(node_type: Option<Box<Type>>, size: usize) -> Option<Box<T
 pub fn new_type(
     ty: TypeKind,
     size: usize,
+    align: usize,
     ptr_to: Option<Box<Type>>,
     array_size: usize,
 ) -> Option<Box<Type>> {
     Some(Box::new(Type {
         ty,
         size,
+        align,
         ptr_to,
         array_size,
+        is_unsigned: false,
+        is_const: false,
+        is_volatile: false,
+        is_restrict: false,
+        params: Vec::new(),
+        is_variadic: false,
+        layout: None,
     }))
 }
 
+// the type of `cond ? lhs : rhs`, or None if the operands have none in
+// common; a pointer on either side wins so that `p ? p : 0` stays a pointer,
+// though not a null pointer constant over another pointer, and arrays and
+// functions decay to pointers
+fn common_type(lhs: &Node, rhs: &Node) -> Option<Box<Type>> {
+    let (lhs, rhs) = if is_null_pointer(lhs) {
+        (rhs, lhs)
+    } else {
+        (lhs, rhs)
+    };
+    for node in [lhs, rhs] {
+        let ty = node.var_type.as_ref().unwrap();
+        match ty.ty {
+            TypeKind::TyPtr => return Some(ty.clone()),
+            TypeKind::TyArray => return new_type_ptr(ty.ptr_to.clone()),
+            TypeKind::TyFunc => return new_type_ptr(Some(ty.clone())),
+            _ => {}
+        }
+    }
+    let lty = lhs.var_type.as_ref().unwrap();
+    let rty = rhs.var_type.as_ref().unwrap();
+    let is_arith = |ty: &Type| is_integer(ty) || is_float(ty);
+    if is_arith(lty) && is_arith(rty) {
+        return arith_type(lty, rty);
+    }
+    // both operands must be void, or the same struct or union
+    let same_struct = lty.layout.is_some() && lty.layout == rty.layout;
+    if same_struct || (lty.ty == TypeKind::TyVoid && rty.ty == TypeKind::TyVoid) {
+        return Some(lty.clone());
+    }
+    None
+}
+
+// an integer constant expression that is 0, or one cast to void *
+fn is_null_pointer(node: &Node) -> bool {
+    let ty = node.var_type.as_ref().unwrap();
+    if ty.ty == TypeKind::TyPtr
+        && ty.ptr_to.as_ref().unwrap().ty == TypeKind::TyVoid
+        && matches!(node.kind, NodeKind::NdCast)
+    {
+        return is_null_pointer(node.lhs.as_ref().unwrap());
+    }
+    is_integer(ty) && eval(node) == Some(0)
+}
+
 pub fn add_type(node: &mut Node) {
     if node.var_type.is_some() {
         return;
@@ -68,17 +274,43 @@ pub fn add_type(node: &mut Node) {
     }
     match node.kind {
         NodeKind::NdNum => node.var_type = new_type_int(),
-        NodeKind::NdAdd | NodeKind::NdSub | NodeKind::NdMul | NodeKind::NdDiv => {
+        NodeKind::NdAdd
+        | NodeKind::NdSub
+        | NodeKind::NdMul
+        | NodeKind::NdDiv
+        | NodeKind::NdMod
+        | NodeKind::NdBitAnd
+        | NodeKind::NdBitOr
+        | NodeKind::NdBitXor => {
+            let lty = node.lhs.as_ref().unwrap().var_type.as_ref().unwrap();
+            let rty = node.rhs.as_ref().unwrap().var_type.as_ref().unwrap();
+            node.var_type = if is_integer(lty) && is_integer(rty) {
+                arith_type(lty, rty)
+            } else {
+                Some(lty.clone())
+            };
+        }
+        // the result of a shift has the type of its promoted left operand
+        NodeKind::NdShl | NodeKind::NdShr => {
+            let lty = node.lhs.as_ref().unwrap().var_type.as_ref().unwrap();
+            node.var_type = arith_type(lty, lty);
+        }
+        NodeKind::NdAssign | NodeKind::NdAssignOp | NodeKind::NdPostOp => {
             node.var_type = node.lhs.as_ref().unwrap().var_type.clone()
         }
-        NodeKind::NdAssign => node.var_type = node.lhs.as_ref().unwrap().var_type.clone(),
         NodeKind::NdEq
         | NodeKind::NdNe
         | NodeKind::NdLt
         | NodeKind::NdGt
         | NodeKind::NdGe
-        | NodeKind::NdLe => node.var_type = new_type_int(),
-        NodeKind::NdNeg => node.var_type = node.rhs.as_ref().unwrap().var_type.clone(),
+        | NodeKind::NdLe
+        | NodeKind::NdLogAnd
+        | NodeKind::NdLogOr
+        | NodeKind::NdNot => node.var_type = new_type_int(),
+        NodeKind::NdNeg | NodeKind::NdBitNot => {
+            let rty = node.rhs.as_ref().unwrap().var_type.as_ref().unwrap();
+            node.var_type = arith_type(rty, rty);
+        }
         NodeKind::NdAddr => {
             if node.rhs.clone().unwrap().var_type.as_ref().unwrap().ty == TypeKind::TyArray {
                 node.var_type = new_type_ptr(
@@ -95,17 +327,171 @@ pub fn add_type(node: &mut Node) {
                 node.var_type = new_type_ptr(node.rhs.clone().unwrap().var_type.clone());
             }
         }
+        NodeKind::NdLabelVal => node.var_type = new_type_ptr(new_type_int()),
+        NodeKind::NdComma => node.var_type = node.rhs.as_ref().unwrap().var_type.clone(),
+        NodeKind::NdCond => {
+            let branches = node.rhs.as_ref().unwrap();
+            node.var_type = common_type(
+                branches.lhs.as_ref().unwrap(),
+                branches.rhs.as_ref().unwrap(),
+            );
+        }
         NodeKind::NdDeref => {
-            node.var_type = node
-                .rhs
-                .as_ref()
-                .unwrap()
-                .var_type
-                .as_ref()
-                .unwrap()
-                .ptr_to
-                .clone();
+            let rty = node.rhs.as_ref().unwrap().var_type.as_ref().unwrap();
+            // a function stands for its address, so *f is f again
+            node.var_type = if rty.ty == TypeKind::TyFunc {
+                Some(rty.clone())
+            } else {
+                rty.ptr_to.clone()
+            };
         }
         _ => {}
     }
 }
+
+// evaluate a constant expression, or None if node is not one
+pub fn eval(node: &Node) -> Option<i64> {
+    eval2(node, None)
+}
+
+// like eval, but the result may also be the address of a global plus a
+// constant, in which case the name of the global is stored to label
+pub fn eval_reloc(node: &Node, label: &mut Option<String>) -> Option<i64> {
+    eval2(node, Some(label))
+}
+
+// the result of every operator is wrapped to its type, so that values of
+// types narrower than 64 bits are kept sign- or zero-extended as in codegen
+fn eval2(node: &Node, label: Option<&mut Option<String>>) -> Option<i64> {
+    let val = eval3(node, label)?;
+    Some(wrap(val, node.var_type.as_ref().unwrap()))
+}
+
+fn eval3(node: &Node, label: Option<&mut Option<String>>) -> Option<i64> {
+    let lhs = || eval(node.lhs.as_ref().unwrap());
+    let rhs = || eval(node.rhs.as_ref().unwrap());
+    let is_unsigned = node.var_type.as_ref().is_some_and(|ty| ty.is_unsigned);
+    match node.kind {
+        NodeKind::NdNum => Some(node.val),
+        NodeKind::NdAdd | NodeKind::NdSub => {
+            let lhs_node = node.lhs.as_ref().unwrap();
+            let lhs = eval2(lhs_node, label)?;
+            let mut rhs = rhs()?;
+            // pointer arithmetic is scaled by the size of the pointee
+            let lty = lhs_node.var_type.as_ref().unwrap();
+            if !is_integer(lty) {
+                rhs = rhs.wrapping_mul(lty.ptr_to.as_ref().unwrap().size() as i64);
+            }
+            if let NodeKind::NdAdd = node.kind {
+                Some(lhs.wrapping_add(rhs))
+            } else {
+                Some(lhs.wrapping_sub(rhs))
+            }
+        }
+        NodeKind::NdNeg => Some(lhs()?.wrapping_sub(rhs()?)),
+        NodeKind::NdMul => Some(lhs()?.wrapping_mul(rhs()?)),
+        NodeKind::NdDiv if is_unsigned => Some((lhs()? as u64).checked_div(rhs()? as u64)? as i64),
+        NodeKind::NdMod if is_unsigned => Some((lhs()? as u64).checked_rem(rhs()? as u64)? as i64),
+        NodeKind::NdDiv => lhs()?.checked_div(rhs()?),
+        NodeKind::NdMod => lhs()?.checked_rem(rhs()?),
+        NodeKind::NdBitAnd => Some(lhs()? & rhs()?),
+        NodeKind::NdBitOr => Some(lhs()? | rhs()?),
+        NodeKind::NdBitXor => Some(lhs()? ^ rhs()?),
+        NodeKind::NdShl => Some(lhs()?.wrapping_shl(rhs()? as u32)),
+        NodeKind::NdShr if is_unsigned => Some((lhs()? as u64).wrapping_shr(rhs()? as u32) as i64),
+        NodeKind::NdShr => Some(lhs()?.wrapping_shr(rhs()? as u32)),
+        NodeKind::NdEq
+        | NodeKind::NdNe
+        | NodeKind::NdLt
+        | NodeKind::NdLe
+        | NodeKind::NdGt
+        | NodeKind::NdGe => {
+            // both sides are converted to the type they have in common
+            let ty = cmp_type(node);
+            let lhs = wrap(lhs()?, &ty);
+            let rhs = wrap(rhs()?, &ty);
+            let ordering = if ty.is_unsigned {
+                (lhs as u64).cmp(&(rhs as u64))
+            } else {
+                lhs.cmp(&rhs)
+            };
+            Some(match node.kind {
+                NodeKind::NdEq => ordering.is_eq(),
+                NodeKind::NdNe => ordering.is_ne(),
+                NodeKind::NdLt => ordering.is_lt(),
+                NodeKind::NdLe => ordering.is_le(),
+                NodeKind::NdGt => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            } as i64)
+        }
+        NodeKind::NdLogAnd => Some((lhs()? != 0 && rhs()? != 0) as i64),
+        NodeKind::NdLogOr => Some((lhs()? != 0 || rhs()? != 0) as i64),
+        NodeKind::NdNot => Some((rhs()? == 0) as i64),
+        NodeKind::NdBitNot => Some(!rhs()?),
+        NodeKind::NdComma => eval2(node.rhs.as_ref().unwrap(), label),
+        NodeKind::NdCast => eval2(node.lhs.as_ref().unwrap(), label),
+        NodeKind::NdCond => {
+            let branches = node.rhs.as_ref().unwrap();
+            if lhs()? != 0 {
+                eval2(branches.lhs.as_ref().unwrap(), label)
+            } else {
+                eval2(branches.rhs.as_ref().unwrap(), label)
+            }
+        }
+        NodeKind::NdAddr => eval_addr(node.rhs.as_ref().unwrap(), label?),
+        // an array evaluates to the address of its first element
+        NodeKind::NdGvar if node.var_type.as_ref().unwrap().ty == TypeKind::TyArray => {
+            eval_addr(node, label?)
+        }
+        _ => None,
+    }
+}
+
+// val converted to the integer type ty; other types keep all 64 bits
+pub fn wrap(val: i64, ty: &Type) -> i64 {
+    if ty.ty == TypeKind::TyBool {
+        return (val != 0) as i64;
+    }
+    if !is_integer(ty) {
+        return val;
+    }
+    match (ty.size(), ty.is_unsigned) {
+        (1, false) => val as i8 as i64,
+        (1, true) => val as u8 as i64,
+        (2, false) => val as i16 as i64,
+        (2, true) => val as u16 as i64,
+        (4, false) => val as i32 as i64,
+        (4, true) => val as u32 as i64,
+        _ => val,
+    }
+}
+
+// the type the operands of the comparison node are compared in; pointers
+// compare as unsigned addresses
+pub fn cmp_type(node: &Node) -> Type {
+    let lty = node.lhs.as_ref().unwrap().var_type.as_ref().unwrap();
+    let rty = node.rhs.as_ref().unwrap().var_type.as_ref().unwrap();
+    if is_integer(lty) && is_integer(rty) {
+        *arith_type(lty, rty).unwrap()
+    } else {
+        *new_type_unsigned(new_type_long()).unwrap()
+    }
+}
+
+// the address of the lvalue node as a global label plus a constant offset
+fn eval_addr(node: &Node, label: &mut Option<String>) -> Option<i64> {
+    match node.kind {
+        NodeKind::NdGvar => {
+            *label = Some(node.name.clone());
+            Some(0)
+        }
+        // &*p is p
+        NodeKind::NdDeref => eval_reloc(node.rhs.as_ref().unwrap(), label),
+        // which is how offsetof computes a member's offset from a null pointer
+        NodeKind::NdMember => {
+            let base = eval_addr(node.lhs.as_ref().unwrap(), label)?;
+            Some(base + node.offset as i64)
+        }
+        _ => None,
+    }
+}
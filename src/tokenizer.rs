@@ -1,10 +1,12 @@
-use crate::util::{error_at, USER_INPUT};
+use crate::sema::{new_type_int, new_type_long, new_type_unsigned, Type};
+use crate::util::{error_at, SOURCE_FILES};
 
-#[derive(PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum TokenKind {
     TkReserved,
     TkIdent,
     TkNum,
+    TkStr,
     TkReturn,
     TkIf,
     TkElse,
@@ -12,272 +14,490 @@ pub enum TokenKind {
     TkInt,
     TkFor,
     TkSizeof,
+    TkAlignof,
+    TkBreak,
+    TkContinue,
+    TkDo,
+    TkSwitch,
+    TkCase,
+    TkDefault,
+    TkGoto,
+    TkStatic,
+    TkExtern,
+    TkConst,
+    TkVolatile,
+    TkRestrict,
+    TkVoid,
+    TkBool,
+    TkChar,
+    TkShort,
+    TkLong,
+    TkFloat,
+    TkDouble,
+    TkSigned,
+    TkUnsigned,
+    TkStruct,
+    TkUnion,
+    TkTypedef,
+    TkInline,
+    TkNoreturn,
+    TkExtension,
+    TkAttribute,
+    TkAsm,
+    TkBuiltinVaList,
+    TkInvalid, // an unclosed literal, an error only if it is not skipped
     TkEof,
 }
 
 pub struct Token {
     pub kind: TokenKind,
     pub next: Option<Box<Token>>,
-    pub val: Option<i32>,
+    pub val: Option<i64>,
+    pub ty: Option<Box<Type>>, // the type of a number
     pub str: String,
-    pub loc: usize, // token location in input
+    pub loc: usize,      // token location in input
+    pub file: usize,     // index of the source file the token came from
+    pub line: usize,     // line number in that file
+    pub at_bol: bool,    // first token on its line
+    pub has_space: bool, // preceded by whitespace
+    // macros this token came out of, which must not expand it again
+    pub hideset: Vec<String>,
+    // file and line of the outermost macro invocation the token came from
+    pub origin: Option<(usize, usize)>,
 }
 
 impl Token {
-    fn new(kind: TokenKind, val: Option<i32>, str: String, loc: usize) -> Self {
+    fn new(kind: TokenKind, val: Option<i64>, str: String, loc: usize) -> Self {
         Token {
             kind,
             next: None,
             val,
+            ty: None,
             str,
             loc,
+            file: 0,
+            line: 0,
+            at_bol: false,
+            has_space: false,
+            hideset: Vec::new(),
+            origin: None,
         }
     }
-}
 
-fn new_token(kind: TokenKind, cur: &mut Token, str: String, loc: usize) -> &mut Token {
-    let tok = Token::new(kind, None, str.to_string(), loc);
-    cur.next = Some(Box::new(tok));
-    cur.next.as_mut().unwrap()
+    // a copy of the token alone, without the rest of the list
+    pub fn copy(&self) -> Token {
+        Token {
+            kind: self.kind.clone(),
+            next: None,
+            val: self.val,
+            ty: self.ty.clone(),
+            str: self.str.clone(),
+            loc: self.loc,
+            file: self.file,
+            line: self.line,
+            at_bol: self.at_bol,
+            has_space: self.has_space,
+            hideset: self.hideset.clone(),
+            origin: self.origin,
+        }
+    }
 }
 
-pub fn tokenizer(input: &str) -> Option<Box<Token>> {
-    let mut head = Token::new(TokenKind::TkEof, None, String::new(), 0);
-    let mut cur = &mut head;
-    let mut chars = input.chars().peekable();
+const PUNCTS: &[&str] = &[
+    "...", "<<=", ">>=", "==", "!=", "<=", ">=", "&&", "||", "<<", ">>", "&=", "|=", "^=", "%=",
+    "+=", "-=", "*=", "/=", "++", "--", "->", "##", "+", "-", "*", "/", "(", ")", "{", "}", ";",
+    ",", "[", "]", "=", "<", ">", "!", "&", "|", "^", "~", "%", "?", ":", ".", "#",
+];
 
-    *USER_INPUT.write().unwrap() = Some(input.to_string());
+const KEYWORDS: &[(&str, TokenKind)] = &[
+    ("return", TokenKind::TkReturn),
+    ("if", TokenKind::TkIf),
+    ("else", TokenKind::TkElse),
+    ("while", TokenKind::TkWhile),
+    ("for", TokenKind::TkFor),
+    ("int", TokenKind::TkInt),
+    ("sizeof", TokenKind::TkSizeof),
+    ("_Alignof", TokenKind::TkAlignof),
+    ("alignof", TokenKind::TkAlignof),
+    ("break", TokenKind::TkBreak),
+    ("continue", TokenKind::TkContinue),
+    ("do", TokenKind::TkDo),
+    ("switch", TokenKind::TkSwitch),
+    ("case", TokenKind::TkCase),
+    ("default", TokenKind::TkDefault),
+    ("goto", TokenKind::TkGoto),
+    ("static", TokenKind::TkStatic),
+    ("extern", TokenKind::TkExtern),
+    ("const", TokenKind::TkConst),
+    ("volatile", TokenKind::TkVolatile),
+    ("restrict", TokenKind::TkRestrict),
+    ("void", TokenKind::TkVoid),
+    ("_Bool", TokenKind::TkBool),
+    ("char", TokenKind::TkChar),
+    ("short", TokenKind::TkShort),
+    ("long", TokenKind::TkLong),
+    ("float", TokenKind::TkFloat),
+    ("double", TokenKind::TkDouble),
+    ("signed", TokenKind::TkSigned),
+    ("unsigned", TokenKind::TkUnsigned),
+    ("struct", TokenKind::TkStruct),
+    ("union", TokenKind::TkUnion),
+    ("typedef", TokenKind::TkTypedef),
+    ("inline", TokenKind::TkInline),
+    ("_Noreturn", TokenKind::TkNoreturn),
+    // GNU spellings, which system headers use
+    ("__const", TokenKind::TkConst),
+    ("__const__", TokenKind::TkConst),
+    ("__volatile", TokenKind::TkVolatile),
+    ("__volatile__", TokenKind::TkVolatile),
+    ("__restrict", TokenKind::TkRestrict),
+    ("__restrict__", TokenKind::TkRestrict),
+    ("__signed", TokenKind::TkSigned),
+    ("__signed__", TokenKind::TkSigned),
+    ("__inline", TokenKind::TkInline),
+    ("__inline__", TokenKind::TkInline),
+    ("__extension__", TokenKind::TkExtension),
+    ("__attribute__", TokenKind::TkAttribute),
+    ("__attribute", TokenKind::TkAttribute),
+    ("__asm__", TokenKind::TkAsm),
+    ("__asm", TokenKind::TkAsm),
+    ("asm", TokenKind::TkAsm),
+    ("__builtin_va_list", TokenKind::TkBuiltinVaList),
+];
+
+// the preprocessor sees keywords as identifiers, so they are only told
+// apart once it is done
+pub fn convert_keywords(mut token: &mut Option<Box<Token>>) {
+    while let Some(current) = token {
+        if current.kind == TokenKind::TkIdent {
+            if let Some((_, kind)) = KEYWORDS.iter().find(|(keyword, _)| *keyword == current.str) {
+                current.kind = kind.clone();
+            }
+        }
+        token = &mut current.next;
+    }
+}
 
-    while let Some(&c) = chars.peek() {
-        if c.is_whitespace() {
-            chars.next();
+// tokenize the registered source file with the given index
+pub fn tokenizer(file: usize) -> Option<Box<Token>> {
+    let (input, base) = {
+        let files = SOURCE_FILES.read().unwrap();
+        (files[file].contents.clone(), files[file].base)
+    };
+    let input = &input[..];
+    let bytes = input.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    let mut line = 1;
+    let mut at_bol = true;
+    let mut has_space = false;
+
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        if c == b'\n' {
+            pos += 1;
+            line += 1;
+            at_bol = true;
+            has_space = false;
+            continue;
+        }
+        // a backslash at the end of a line joins it with the next one
+        if c == b'\\' && bytes.get(pos + 1) == Some(&b'\n') {
+            pos += 2;
+            line += 1;
+            has_space = true;
+            continue;
+        }
+        if c.is_ascii_whitespace() {
+            pos += 1;
+            has_space = true;
+            continue;
+        }
+        if input[pos..].starts_with("//") {
+            pos = input[pos..].find('\n').map_or(bytes.len(), |i| pos + i);
+            has_space = true;
+            continue;
+        }
+        if input[pos..].starts_with("/*") {
+            let end = match input[pos + 2..].find("*/") {
+                Some(i) => pos + 2 + i + 2,
+                None => error_at(base + pos, "unclosed block comment"),
+            };
+            line += input[pos..end].matches('\n').count();
+            pos = end;
+            has_space = true;
             continue;
         }
 
-        if c == '=' {
-            // consume "==" or "="
-            chars.next();
-            if let Some(&c) = chars.peek() {
-                if c == '=' {
-                    cur = new_token(
-                        TokenKind::TkReserved,
-                        cur,
-                        "==".to_string(),
-                        input.len() - chars.clone().count(),
-                    );
-                    chars.next();
-                    continue;
-                } else {
-                    cur = new_token(
-                        TokenKind::TkReserved,
-                        cur,
-                        "=".to_string(),
-                        input.len() - chars.clone().count(),
-                    );
-                    continue;
+        let start = pos;
+        let kind = if c.is_ascii_digit()
+            || (c == b'.' && bytes.get(pos + 1).is_some_and(u8::is_ascii_digit))
+        {
+            pos = pp_number_end(bytes, pos);
+            TokenKind::TkNum
+        } else if c == b'\'' || c == b'"' {
+            let end = if c == b'\'' {
+                char_literal_end(bytes, pos)
+            } else {
+                string_literal_end(bytes, pos)
+            };
+            match end {
+                Some(end) => {
+                    pos = end;
+                    if c == b'\'' {
+                        TokenKind::TkNum
+                    } else {
+                        TokenKind::TkStr
+                    }
                 }
-            }
-        }
-        if c == '!' {
-            // consume "!="
-            chars.next();
-            if let Some(&c) = chars.peek() {
-                if c == '=' {
-                    cur = new_token(
-                        TokenKind::TkReserved,
-                        cur,
-                        "!=".to_string(),
-                        input.len() - chars.clone().count(),
-                    );
-                    chars.next();
-                    continue;
-                } else {
-                    error_at(input.len() - chars.clone().count(), "invalid token");
+                None => {
+                    // the rest of the line, which may be in a skipped group
+                    pos = input[pos..].find('\n').map_or(bytes.len(), |i| pos + i);
+                    TokenKind::TkInvalid
                 }
             }
-        }
-        if c == '<' {
-            // consume "<=" or "<"
-            chars.next();
-            if let Some(&c) = chars.peek() {
-                if c == '=' {
-                    cur = new_token(
-                        TokenKind::TkReserved,
-                        cur,
-                        "<=".to_string(),
-                        input.len() - chars.clone().count(),
-                    );
-                    chars.next();
-                    continue;
-                } else {
-                    cur = new_token(
-                        TokenKind::TkReserved,
-                        cur,
-                        "<".to_string(),
-                        input.len() - chars.clone().count(),
-                    );
-                    continue;
-                }
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_') {
+                pos += 1;
             }
-        }
-        if c == '>' {
-            // consume ">=" or ">"
-            chars.next();
-            if let Some(&c) = chars.peek() {
-                if c == '=' {
-                    cur = new_token(
-                        TokenKind::TkReserved,
-                        cur,
-                        ">=".to_string(),
-                        input.len() - chars.clone().count(),
-                    );
-                    chars.next();
-                    continue;
-                } else {
-                    cur = new_token(
-                        TokenKind::TkReserved,
-                        cur,
-                        ">".to_string(),
-                        input.len() - chars.clone().count(),
-                    );
-                    continue;
-                }
+            TokenKind::TkIdent
+        } else if let Some(punct) = PUNCTS.iter().find(|p| input[pos..].starts_with(*p)) {
+            // longer punctuators come first so that e.g. "<<=" is not read as "<" "<="
+            pos += punct.len();
+            TokenKind::TkReserved
+        } else {
+            error_at(base + pos, "invalid token");
+        };
+
+        let mut tok = Token::new(kind, None, input[start..pos].to_string(), base + start);
+        if tok.kind == TokenKind::TkNum {
+            if let Ok((val, ty)) = number_value(&tok.str) {
+                tok.val = Some(val);
+                tok.ty = ty;
             }
         }
+        tok.file = file;
+        tok.line = line;
+        tok.at_bol = at_bol;
+        tok.has_space = has_space;
+        tokens.push(tok);
+        at_bol = false;
+        has_space = false;
+    }
+
+    let mut eof = Token::new(TokenKind::TkEof, None, String::new(), base + bytes.len());
+    eof.file = file;
+    eof.line = line;
+    eof.at_bol = true;
+    tokens.push(eof);
+    link_tokens(tokens)
+}
 
-        if c == '+'
-            || c == '-'
-            || c == '*'
-            || c == '/'
-            || c == '('
-            || c == ')'
-            || c == '{'
-            || c == '}'
-            || c == ';'
-            || c == ','
-            || c == '&'
-            || c == '['
-            || c == ']'
+// the position just past the preprocessing number starting at pos, which
+// takes in anything a number could continue with, such as 1e+5 or 0x1fUL
+fn pp_number_end(bytes: &[u8], mut pos: usize) -> usize {
+    while pos < bytes.len() {
+        let c = bytes[pos];
+        if matches!(c, b'e' | b'E' | b'p' | b'P')
+            && matches!(bytes.get(pos + 1), Some(b'+') | Some(b'-'))
         {
-            cur = new_token(
-                TokenKind::TkReserved,
-                cur,
-                c.to_string(),
-                input.len() - chars.clone().count(),
-            );
-            chars.next();
-            continue;
+            pos += 2;
+        } else if c.is_ascii_alphanumeric() || c == b'_' || c == b'.' {
+            pos += 1;
+        } else {
+            break;
         }
+    }
+    pos
+}
 
-        if c.is_digit(10) {
-            let mut num_str = String::new();
-            while let Some(&c) = chars.peek() {
-                if c.is_digit(10) {
-                    num_str.push(c);
-                    chars.next();
-                } else {
-                    break;
-                }
-            }
-            cur = new_token(
-                TokenKind::TkNum,
-                cur,
-                num_str.to_string(),
-                input.len() - chars.clone().count(),
-            );
-            cur.val = Some(num_str.parse().unwrap());
-            continue;
+// the value and type of an integer constant or a character literal, or why
+// the number is not one; a pp-number only has to be valid where the parser
+// uses it, so the tokenizer keeps just the value
+pub fn number_value(str: &str) -> Result<(i64, Option<Box<Type>>), String> {
+    if let Some(body) = str.strip_prefix('\'') {
+        let (c, _) = read_char(body.as_bytes(), 0);
+        // char is signed, and a character constant has type int
+        return Ok((c as i8 as i64, new_type_int()));
+    }
+    let (radix, name, digits) =
+        if let Some(digits) = str.strip_prefix("0x").or_else(|| str.strip_prefix("0X")) {
+            (16, "hexadecimal", digits)
+        } else if let Some(digits) = str.strip_prefix("0b").or_else(|| str.strip_prefix("0B")) {
+            (2, "binary", digits)
+        } else if str.starts_with('0') {
+            (8, "octal", str)
+        } else {
+            (10, "decimal", str)
+        };
+    let end = digits
+        .find(|c: char| !c.is_digit(radix))
+        .unwrap_or(digits.len());
+    let (digits, suffix) = digits.split_at(end);
+    let is_float = if radix == 16 {
+        suffix.starts_with(['.', 'p', 'P'])
+    } else {
+        suffix
+            .trim_start_matches(|c: char| c.is_ascii_digit())
+            .starts_with(['.', 'e', 'E'])
+    };
+    if is_float {
+        return Err("floating-point constants are not supported".to_string());
+    }
+    if digits.is_empty() {
+        return Err(format!("{} constant has no digits", name));
+    }
+    if let Some(digit) = suffix.chars().next().filter(char::is_ascii_digit) {
+        return Err(format!("invalid digit '{}' in {} constant", digit, name));
+    }
+    let (is_unsigned, is_long) = match integer_suffix(suffix) {
+        Some(suffix) => suffix,
+        None => return Err(format!("invalid suffix '{}' on integer constant", suffix)),
+    };
+    let val = match u64::from_str_radix(digits, radix) {
+        Ok(val) => val,
+        Err(_) => {
+            return Err("integer constant is too large for any integer type".to_string());
         }
+    };
 
-        if c.is_alphabetic() {
-            let mut ident_str = String::new();
-            while let Some(&c) = chars.peek() {
-                if c.is_alphanumeric() {
-                    ident_str.push(c);
-                    chars.next();
-                } else {
-                    break;
-                }
-            }
-            if ident_str == "return" {
-                cur = new_token(
-                    TokenKind::TkReturn,
-                    cur,
-                    ident_str.to_string(),
-                    input.len() - chars.clone().count(),
-                );
-                continue;
-            }
-            if ident_str == "if" {
-                cur = new_token(
-                    TokenKind::TkIf,
-                    cur,
-                    ident_str.to_string(),
-                    input.len() - chars.clone().count(),
-                );
-                continue;
-            }
-            if ident_str == "else" {
-                cur = new_token(
-                    TokenKind::TkElse,
-                    cur,
-                    ident_str.to_string(),
-                    input.len() - chars.clone().count(),
-                );
-                continue;
-            }
-            if ident_str == "while" {
-                cur = new_token(
-                    TokenKind::TkWhile,
-                    cur,
-                    ident_str.to_string(),
-                    input.len() - chars.clone().count(),
-                );
-                continue;
-            }
-            if ident_str == "for" {
-                cur = new_token(
-                    TokenKind::TkFor,
-                    cur,
-                    ident_str.to_string(),
-                    input.len() - chars.clone().count(),
-                );
-                continue;
-            }
-            if ident_str == "int" {
-                cur = new_token(
-                    TokenKind::TkInt,
-                    cur,
-                    ident_str.to_string(),
-                    input.len() - chars.clone().count(),
-                );
-                continue;
+    // the first type that can represent the value, from a list that depends
+    // on the suffix and, for hex, octal and binary, also includes the
+    // unsigned types (C11 6.4.4.1p5)
+    let fits_int = val <= i32::MAX as u64;
+    let fits_uint = val <= u32::MAX as u64;
+    let fits_long = val <= i64::MAX as u64;
+    let decimal = radix == 10;
+    let ty = match (is_unsigned, is_long) {
+        (false, false) if fits_int => new_type_int(),
+        (false, false) if fits_uint && !decimal => new_type_unsigned(new_type_int()),
+        (true, false) if fits_uint => new_type_unsigned(new_type_int()),
+        (false, _) if fits_long => new_type_long(),
+        (false, _) if decimal => {
+            return Err("integer constant is too large for a signed integer type".to_string());
+        }
+        _ => new_type_unsigned(new_type_long()),
+    };
+    Ok((val as i64, ty))
+}
+
+// whether the suffix of an integer constant makes it unsigned and whether
+// it makes it long; long long is the same size as long here
+fn integer_suffix(suffix: &str) -> Option<(bool, bool)> {
+    let (is_unsigned, rest) = if let Some(rest) = suffix
+        .strip_prefix(['u', 'U'])
+        .or_else(|| suffix.strip_suffix(['u', 'U']))
+    {
+        (true, rest)
+    } else {
+        (false, suffix)
+    };
+    match rest {
+        "" => Some((is_unsigned, false)),
+        "l" | "L" | "ll" | "LL" => Some((is_unsigned, true)),
+        _ => None,
+    }
+}
+
+// the position just past the character literal starting at pos, or None
+// if it is empty or unclosed
+fn char_literal_end(bytes: &[u8], pos: usize) -> Option<usize> {
+    if matches!(bytes.get(pos + 1), None | Some(b'\n') | Some(b'\'')) {
+        return None;
+    }
+    let (_, end) = read_char(bytes, pos + 1);
+    if bytes.get(end) != Some(&b'\'') {
+        return None;
+    }
+    Some(end + 1)
+}
+
+// what is wrong with a TkInvalid token
+pub fn invalid_literal(tok: &Token) -> &'static str {
+    if tok.str.starts_with('"') {
+        "unclosed string literal"
+    } else if tok.str.len() == 1 || tok.str.starts_with("''") {
+        "empty or unclosed character literal"
+    } else {
+        "unclosed character literal"
+    }
+}
+
+// the byte a possibly escaped character at pos stands for, and the
+// position after it
+fn read_char(bytes: &[u8], pos: usize) -> (u8, usize) {
+    if bytes[pos] != b'\\' {
+        return (bytes[pos], pos + 1);
+    }
+    let mut end = pos + 1;
+    let c = match bytes.get(end) {
+        Some(c) => *c,
+        None => return (b'\\', end),
+    };
+    end += 1;
+    match c {
+        b'0'..=b'7' => {
+            // up to three octal digits
+            let mut val = (c - b'0') as u32;
+            while end < pos + 4 && matches!(bytes.get(end), Some(b'0'..=b'7')) {
+                val = val * 8 + (bytes[end] - b'0') as u32;
+                end += 1;
             }
-            if ident_str == "sizeof" {
-                cur = new_token(
-                    TokenKind::TkSizeof,
-                    cur,
-                    ident_str.to_string(),
-                    input.len() - chars.clone().count(),
-                );
-                continue;
+            (val as u8, end)
+        }
+        b'x' => {
+            let mut val: u32 = 0;
+            while let Some(digit) = bytes.get(end).and_then(|c| (*c as char).to_digit(16)) {
+                val = val.wrapping_mul(16) + digit;
+                end += 1;
             }
-            cur = new_token(
-                TokenKind::TkIdent,
-                cur,
-                ident_str.to_string(),
-                input.len() - chars.clone().count(),
-            );
-            continue;
+            (val as u8, end)
         }
+        b'a' => (7, end),
+        b'b' => (8, end),
+        b't' => (b'\t', end),
+        b'n' => (b'\n', end),
+        b'v' => (11, end),
+        b'f' => (12, end),
+        b'r' => (b'\r', end),
+        b'e' => (27, end),
+        _ => (c, end),
+    }
+}
 
-        if cur.loc == 0 {
-            error_at(cur.loc, "invalid token");
-        } else {
-            error_at(cur.loc + 1, "invalid token");
+// the bytes a string literal token stands for, without the terminating nul
+pub fn string_contents(tok: &Token) -> Vec<u8> {
+    let bytes = tok.str.as_bytes();
+    let mut contents = Vec::new();
+    let mut pos = 1;
+    while pos < bytes.len() - 1 {
+        let (c, next) = read_char(bytes, pos);
+        contents.push(c);
+        pos = next;
+    }
+    contents
+}
+
+// the position just past the string literal starting at pos, or None if
+// it is unclosed
+fn string_literal_end(bytes: &[u8], pos: usize) -> Option<usize> {
+    let mut end = pos + 1;
+    loop {
+        match bytes.get(end) {
+            None | Some(b'\n') => return None,
+            Some(b'"') => return Some(end + 1),
+            Some(b'\\') => end += 2,
+            Some(_) => end += 1,
         }
     }
+}
 
-    new_token(TokenKind::TkEof, cur, String::new(), input.len());
-    head.next
+// chain tokens into a list; built from the back so that no step recurses
+pub fn link_tokens(tokens: Vec<Token>) -> Option<Box<Token>> {
+    let mut head = None;
+    for mut tok in tokens.into_iter().rev() {
+        tok.next = head;
+        head = Some(Box::new(tok));
+    }
+    head
 }
@@ -1,29 +1,167 @@
 use lazy_static::lazy_static;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicIsize, AtomicUsize, Ordering};
 use std::sync::RwLock;
-use std::{process, usize};
+use std::{panic, process, usize};
 
-use crate::lvar::LVar;
-use crate::tokenizer::{Token, TokenKind};
+use crate::lvar::{LVar, Scope};
+use crate::tokenizer::{number_value, string_contents, Token, TokenKind};
 
 lazy_static! {
-    pub static ref USER_INPUT: RwLock<Option<String>> = RwLock::new(None);
+    // every source file read so far; a location is an offset into all of
+    // them laid end to end, so that it alone identifies a file and line
+    pub static ref SOURCE_FILES: RwLock<Vec<SourceFile>> = RwLock::new(Vec::new());
+    // #line directives, in the order they were read
+    static ref LINE_DIRECTIVES: RwLock<Vec<LineDirective>> = RwLock::new(Vec::new());
 }
 
+// from the line after line in file on, lines are numbered from new_line
+// and said to be in the file called name
+struct LineDirective {
+    file: usize,
+    line: usize,
+    new_line: usize,
+    name: String,
+}
+
+pub struct SourceFile {
+    pub name: String,
+    pub contents: String,
+    pub base: usize, // location of the first byte of contents
+}
+
+// register a source file and return its index
+pub fn add_source_file(name: &str, contents: String) -> usize {
+    let mut files = SOURCE_FILES.write().unwrap();
+    // leave a gap so the end of one file is not the start of the next
+    let base = files
+        .last()
+        .map_or(0, |file| file.base + file.contents.len() + 1);
+    files.push(SourceFile {
+        name: name.to_string(),
+        contents,
+        base,
+    });
+    files.len() - 1
+}
+
+pub fn add_line_directive(file: usize, line: usize, new_line: usize, name: Option<String>) {
+    let name = name.unwrap_or_else(|| presumed_location(file, line).0);
+    LINE_DIRECTIVES.write().unwrap().push(LineDirective {
+        file,
+        line,
+        new_line,
+        name,
+    });
+}
+
+// the file name and line number that line of file goes by, which #line may
+// have changed
+pub fn presumed_location(file: usize, line: usize) -> (String, usize) {
+    let directives = LINE_DIRECTIVES.read().unwrap();
+    match directives
+        .iter()
+        .rev()
+        .find(|directive| directive.file == file && directive.line < line)
+    {
+        Some(directive) => (
+            directive.name.clone(),
+            directive.new_line + line - directive.line - 1,
+        ),
+        None => (SOURCE_FILES.read().unwrap()[file].name.clone(), line),
+    }
+}
+
+// the number of errors reported so far
+static ERROR_COUNT: AtomicUsize = AtomicUsize::new(0);
+// how many errors to report before giving up, or 0 for no limit
+pub static ERROR_LIMIT: AtomicUsize = AtomicUsize::new(20);
+
+// what an error unwinds with to the point that parsing recovers from it
+struct ErrorReported;
+
 pub fn error(msg: &str) -> ! {
     eprintln!("Error: {}", msg);
-    process::exit(1);
+    report_error()
 }
 
 pub fn error_at(loc: usize, msg: &str) -> ! {
-    if let Some(ref user_input) = *USER_INPUT.write().unwrap() {
-        let input = &user_input;
-        eprintln!("{}", input);
-        eprintln!("{:>width$}^ {}", "", msg, width = loc);
-    } else {
-        eprintln!("Error: {}", msg);
+    print_at(loc, msg);
+    report_error()
+}
+
+// count an error that has been printed and unwind to where parsing can go
+// on, unless there have been too many
+fn report_error() -> ! {
+    let count = ERROR_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+    let limit = ERROR_LIMIT.load(Ordering::Relaxed);
+    if limit != 0 && count >= limit {
+        eprintln!("fatal error: too many errors emitted, stopping now [-ferror-limit=]");
+        exit_if_errors();
     }
-    process::exit(1);
+    unwind_error()
+}
+
+// unwind to the next point that recovers from errors without reporting
+// another one, as when the error left nothing to go on with; resume_unwind
+// does not print a panic message
+pub fn unwind_error() -> ! {
+    panic::resume_unwind(Box::new(ErrorReported))
+}
+
+// run f, or return None if it reported an error that it could not go on
+// after
+pub fn recover<T>(f: impl FnOnce() -> T) -> Option<T> {
+    match panic::catch_unwind(panic::AssertUnwindSafe(f)) {
+        Ok(val) => Some(val),
+        Err(payload) if payload.is::<ErrorReported>() => None,
+        // a bug in the compiler rather than in its input
+        Err(payload) => panic::resume_unwind(payload),
+    }
+}
+
+// if any errors were reported, say how many and exit
+pub fn exit_if_errors() {
+    let count = ERROR_COUNT.load(Ordering::Relaxed);
+    if count > 0 {
+        let plural = if count == 1 { "" } else { "s" };
+        eprintln!("{} error{} generated.", count, plural);
+        process::exit(1);
+    }
+}
+
+pub fn warn_at(loc: usize, msg: &str) {
+    print_at(loc, &format!("warning: {}", msg));
+}
+
+// print the line containing loc with msg pointing at loc
+fn print_at(loc: usize, msg: &str) {
+    let files = SOURCE_FILES.read().unwrap();
+    let index = match files
+        .iter()
+        .rposition(|file| file.base <= loc && loc <= file.base + file.contents.len())
+    {
+        Some(index) => index,
+        None => {
+            eprintln!("Error: {}", msg);
+            return;
+        }
+    };
+    let file = &files[index];
+    let offset = loc - file.base;
+    let start = file.contents[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let end = file.contents[offset..]
+        .find('\n')
+        .map_or(file.contents.len(), |i| offset + i);
+    let line_no = file.contents[..offset].matches('\n').count() + 1;
+    let text = file.contents[start..end].to_string();
+    let column = file.contents[start..offset].chars().count();
+    drop(files);
+
+    let (name, line_no) = presumed_location(index, line_no);
+    let prefix = format!("{}:{}: ", name, line_no);
+    eprintln!("{}{}", prefix, text);
+    let width = prefix.len() + column;
+    eprintln!("{:>width$}^ {}", "", msg, width = width);
 }
 
 pub fn check(op: &str, token: &Option<Box<Token>>) -> bool {
@@ -35,10 +173,23 @@ pub fn check(op: &str, token: &Option<Box<Token>>) -> bool {
     false
 }
 
+// how many "{" have been consumed without the "}" that closes them, which
+// error recovery compares with the count at the start of a statement
+static BRACE_DEPTH: AtomicIsize = AtomicIsize::new(0);
+
+pub fn brace_depth() -> isize {
+    BRACE_DEPTH.load(Ordering::Relaxed)
+}
+
 pub fn consume(op: &str, token: &mut Option<Box<Token>>) -> bool {
     if let Some(current) = token {
         if let TokenKind::TkReserved = current.kind {
             if current.str == op {
+                match op {
+                    "{" => BRACE_DEPTH.fetch_add(1, Ordering::Relaxed),
+                    "}" => BRACE_DEPTH.fetch_sub(1, Ordering::Relaxed),
+                    _ => 0,
+                };
                 *token = current.next.take();
                 return true;
             }
@@ -47,6 +198,13 @@ pub fn consume(op: &str, token: &mut Option<Box<Token>>) -> bool {
     false
 }
 
+pub fn check_kind(kind: TokenKind, token: &Option<Box<Token>>) -> bool {
+    if let Some(current) = token {
+        return kind == current.kind;
+    }
+    false
+}
+
 pub fn consume_kind(kind: TokenKind, token: &mut Option<Box<Token>>) -> bool {
     if let Some(current) = token {
         if kind == current.kind {
@@ -57,6 +215,44 @@ pub fn consume_kind(kind: TokenKind, token: &mut Option<Box<Token>>) -> bool {
     false
 }
 
+// true if the token can start the declaration specifiers of something other
+// than a storage class, which includes names declared with typedef
+pub fn is_typename(token: &Option<Box<Token>>, lvar: &Scope) -> bool {
+    if let Some(current) = token {
+        return matches!(
+            current.kind,
+            TokenKind::TkVoid
+                | TokenKind::TkBool
+                | TokenKind::TkChar
+                | TokenKind::TkShort
+                | TokenKind::TkInt
+                | TokenKind::TkLong
+                | TokenKind::TkFloat
+                | TokenKind::TkDouble
+                | TokenKind::TkSigned
+                | TokenKind::TkUnsigned
+                | TokenKind::TkStruct
+                | TokenKind::TkUnion
+                | TokenKind::TkBuiltinVaList
+                | TokenKind::TkInline
+                | TokenKind::TkNoreturn
+                | TokenKind::TkAttribute
+        ) || is_qualifier(token)
+            || (current.kind == TokenKind::TkIdent && lvar.find_typedef(&current.str).is_some());
+    }
+    false
+}
+
+pub fn is_qualifier(token: &Option<Box<Token>>) -> bool {
+    if let Some(current) = token {
+        return matches!(
+            current.kind,
+            TokenKind::TkConst | TokenKind::TkVolatile | TokenKind::TkRestrict
+        );
+    }
+    false
+}
+
 pub fn expect(op: &str, token: &mut Option<Box<Token>>) {
     if !consume(op, token) {
         if let Some(current) = token {
@@ -65,10 +261,15 @@ pub fn expect(op: &str, token: &mut Option<Box<Token>>) {
     }
 }
 
-pub fn expect_number(token: &mut Option<Box<Token>>) -> i32 {
+pub fn expect_number(token: &mut Option<Box<Token>>) -> i64 {
     if let Some(current) = token {
         if let TokenKind::TkNum = current.kind {
-            let val = current.val.unwrap();
+            // the tokenizer only keeps the value of a valid number, so say
+            // what is wrong with the others now that one is used
+            let val = match current.val {
+                Some(val) => val,
+                None => error_at(current.loc, &number_value(&current.str).unwrap_err()),
+            };
             *token = current.next.take();
             return val;
         }
@@ -91,6 +292,20 @@ pub fn expect_ident(token: &mut Option<Box<Token>>) -> String {
     }
 }
 
+// the bytes of a string literal
+pub fn expect_string(token: &mut Option<Box<Token>>) -> Vec<u8> {
+    if let Some(current) = token {
+        if let TokenKind::TkStr = current.kind {
+            let contents = string_contents(current);
+            *token = current.next.take();
+            return contents;
+        }
+        error_at(current.loc, "expected string literal");
+    } else {
+        error("unexpected error");
+    }
+}
+
 pub fn find_lvar(lvar: &Option<Box<LVar>>, name: &str) -> Option<Box<LVar>> {
     if let Some(current) = lvar {
         if current.name == name {
@@ -102,6 +317,23 @@ pub fn find_lvar(lvar: &Option<Box<LVar>>, name: &str) -> Option<Box<LVar>> {
     }
 }
 
+// the declaration of the global called name that defines it, if any
+pub fn find_gvar_def(gvar: &Option<Box<LVar>>, name: &str) -> Option<Box<LVar>> {
+    if let Some(current) = gvar {
+        if current.name == name && !current.is_extern && !current.is_tentative {
+            return Some(current.clone());
+        }
+        find_gvar_def(&current.next, name)
+    } else {
+        None
+    }
+}
+
+// round n up to the nearest multiple of align
+pub fn align_to(n: i32, align: i32) -> i32 {
+    (n + align - 1) / align * align
+}
+
 pub fn gen_label() -> usize {
     static LABEL: AtomicUsize = AtomicUsize::new(0);
     LABEL.fetch_add(1, Ordering::Relaxed)
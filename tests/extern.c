@@ -0,0 +1,15 @@
+int ext_count = 3;
+int ext_array[3] = {1, 2, 3};
+
+// defined by the program under test when it wants to be called back
+__attribute__((weak)) int ext_callback(int x);
+
+int call_callback(int x) {
+    if (!ext_callback) {
+        return 0;
+    }
+    return ext_callback(x);
+}
+
+// merged with the tentative definition in the program under test
+int ext_common = 7;
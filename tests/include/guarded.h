@@ -0,0 +1,7 @@
+/* a definition, so including it twice would be an error */
+#ifndef GUARDED_H
+#define GUARDED_H
+
+int guarded = 5;
+
+#endif
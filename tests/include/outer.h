@@ -0,0 +1,4 @@
+#pragma once
+#include "sub/inner.h"
+
+int outer() { return inner() + 1; }
@@ -0,0 +1,4 @@
+#pragma once
+#include "leaf.h"
+
+int inner() { return leaf() + 1; }
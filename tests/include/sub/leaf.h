@@ -0,0 +1 @@
+int leaf() { return 1; }
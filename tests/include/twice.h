@@ -0,0 +1,4 @@
+#pragma once
+
+// a definition, so including it twice would be an error
+int twice(int x) { return x * 2; }
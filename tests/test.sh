@@ -1,12 +1,15 @@
 #!/bin/bash
+# any further arguments are passed to the compiler
 assert() {
 	expected="$1"
 	input="$2"
+	shift 2
 
-	cargo run -- "$input" > tmp.s
+	cargo run -- "$@" "$input" > tmp.s
 	cc -c tests/sum.c -o sum.o
 	cc -c tests/alloc4.c -o alloc4.o
-	cc tmp.s sum.o alloc4.o -o tmp -Wa,--noexecstack
+	cc -c tests/extern.c -o extern.o
+	cc tmp.s sum.o alloc4.o extern.o -o tmp -Wa,--noexecstack -Wl,-z,text
 	./tmp
 	actual="$?"
 
@@ -18,6 +21,87 @@ assert() {
 	fi
 }
 
+assert_error() {
+	input="$1"
+	shift
+
+	if cargo run -q -- "$@" "$input" > /dev/null 2>&1; then
+		echo "$input => error expected, but compiled"
+		exit 1
+	else
+		echo "$input => error"
+	fi
+}
+
+# checks how many errors a single run reports
+assert_error_count() {
+	expected="$1"
+	input="$2"
+	shift 2
+
+	if summary=$(cargo run -q -- "$@" "$input" 2>&1 > /dev/null); then
+		echo "$input => error expected, but compiled"
+		exit 1
+	fi
+	summary=$(echo "$summary" | tail -n 1)
+	if [ "$summary" = "$expected" ]; then
+		echo "$input => $summary"
+	else
+		echo "$input => $expected expected, but got $summary"
+		exit 1
+	fi
+}
+
+# checks the location line of the first error a run reports
+assert_error_at() {
+	expected="$1"
+	input="$2"
+	shift 2
+
+	if message=$(cargo run -q -- "$@" "$input" 2>&1 > /dev/null); then
+		echo "$input => error expected, but compiled"
+		exit 1
+	fi
+	message=$(echo "$message" | head -n 1)
+	if [ "$message" = "$expected" ]; then
+		echo "$input => $message"
+	else
+		echo "$input => $expected expected, but got $message"
+		exit 1
+	fi
+}
+
+# counts the lines of the generated assembly that match a pattern
+assert_asm_count() {
+	expected="$1"
+	pattern="$2"
+	input="$3"
+	shift 3
+
+	actual=$(cargo run -q -- "$@" "$input" | grep -c -e "$pattern")
+	if [ "$actual" = "$expected" ]; then
+		echo "$input => $actual x $pattern"
+	else
+		echo "$input => $expected x $pattern expected, but got $actual"
+		exit 1
+	fi
+}
+
+# compares what the compiler prints, as for -E
+assert_output() {
+	expected="$1"
+	input="$2"
+	shift 2
+
+	actual=$(cargo run -q -- "$@" "$input")
+	if [ "$actual" = "$expected" ]; then
+		echo "$input => $actual"
+	else
+		echo "$input => $expected expected, but got $actual"
+		exit 1
+	fi
+}
+
 assert 0 "int main(){0;}"
 assert 42 "int main(){42;}"
 assert 21 "int main(){5+20-4;}"
@@ -25,6 +109,37 @@ assert 41 "int main(){ 12 + 34 - 5 ;}"
 assert 47 'int main(){5+6 * 7;}'
 assert 15 'int main(){5*(9-6);}'
 assert 4 'int main(){(3+ 5)/2;}'
+assert 1 'int main(){10%3;}'
+assert 0 'int main(){12%4;}'
+assert 5 'int main(){5%7;}'
+assert 7 'int main(){1+20%7;}'
+assert 1 'int main(){return -7%3 == -1;}'
+assert 2 'int main(){int a;a=17;a%=5;return a;}'
+assert 7 'int main(){int a;a=5;a+=2;return a;}'
+assert 3 'int main(){int a;a=5;a-=2;return a;}'
+assert 10 'int main(){int a;a=5;a*=2;return a;}'
+assert 2 'int main(){int a;a=5;a/=2;return a;}'
+assert 9 'int main(){int a;int b;a=5;b=a+=2;return a+b-5;}'
+assert 6 'int main(){int a;a=5;return ++a;}'
+assert 4 'int main(){int a;a=5;return --a;}'
+assert 5 'int main(){int a;a=5;return a++;}'
+assert 5 'int main(){int a;a=5;return a--;}'
+assert 6 'int main(){int a;a=5;a++;return a;}'
+assert 4 'int main(){int a;a=5;a--;return a;}'
+assert 2 'int main(){int a;a=5;return -a++ + 7;}'
+assert 4 'int main(){int *p;alloc4(&p, 1, 2, 4, 8);p+=2;return *p;}'
+assert 2 'int main(){int *p;alloc4(&p, 1, 2, 4, 8);p+=3;p-=2;return *p;}'
+assert 2 'int main(){int *p;alloc4(&p, 1, 2, 4, 8);++p;return *p;}'
+assert 1 'int main(){int *p;alloc4(&p, 1, 2, 4, 8);return *p++;}'
+assert 2 'int main(){int *p;alloc4(&p, 1, 2, 4, 8);p++;return *p;}'
+assert 4 'int main(){int *p;alloc4(&p, 1, 2, 4, 8);p+=3;p--;return *p;}'
+assert 3 'int main(){int a[3];int i;i=0;a[0]=1;a[1]=2;a[i++]+=2;return a[0]+i-1+0*a[1];}'
+assert 6 'int main(){int a[3];int *p;a[0]=1;a[1]=2;a[2]=3;p=a;return *p++ + *p++ + *p;}'
+assert 1 'int main(){_Bool b = 1; int r = b++; return r == 1 && b == 1;}'
+assert 1 'int main(){_Bool b = 0; int r = b--; return r == 0 && b == 1;}'
+assert 1 'int main(){char c = 127; int r = c++; return r == 127 && c == -128;}'
+assert 1 'int main(){unsigned char c = 0; int r = c--; return r == 0 && c == 255;}'
+assert 1 'int main(){int a[3] = {1, 2, 3}; int *p = a; int i = 0; int r = *p++; a[i++]++; return r == 1 && *p == 2 && a[0] == 2 && i == 1;}'
 assert 10 'int main(){-10+20;}'
 
 assert 0 'int main(){0==1;}'
@@ -46,6 +161,71 @@ assert 1 'int main(){1>=0;}'
 assert 1 'int main(){1>=1;}'
 assert 0 'int main(){1>=2;}'
 
+assert 0 'int main(){return !1;}'
+assert 1 'int main(){return !0;}'
+assert 0 'int main(){return !42;}'
+assert 1 'int main(){return !!42;}'
+assert 1 'int main(){return 1 && 2;}'
+assert 0 'int main(){return 1 && 0;}'
+assert 0 'int main(){return 0 && 1;}'
+assert 1 'int main(){return 0 || 2;}'
+assert 0 'int main(){return 0 || 0;}'
+assert 1 'int main(){return 1 || 0;}'
+assert 1 'int main(){return 1 < 2 && 2 < 3 || 0;}'
+assert 3 'int main(){int a;a=3;0 && (a=5);return a;}'
+assert 3 'int main(){int a;a=3;1 || (a=5);return a;}'
+assert 5 'int main(){int a;a=3;1 && (a=5);return a;}'
+assert 0 'int main(){int *p;p=0;return p && *p;}'
+assert 1 'int main(){int x;int *p;x=7;p=&x;return p && *p == 7;}'
+
+assert 1 'int main(){return 3 & 5;}'
+assert 7 'int main(){return 3 | 5;}'
+assert 6 'int main(){return 3 ^ 5;}'
+assert 10 'int main(){return ~-11;}'
+assert 1 'int main(){return ~-1 == 0;}'
+assert 16 'int main(){return 1 << 4;}'
+assert 5 'int main(){return 20 >> 2;}'
+assert 1 'int main(){int a;a=-8;return (a >> 1) == -4;}'
+assert 3 'int main(){return 1 | 2 & 3;}'
+assert 3 'int main(){return 2 ^ 1 & 1 == 1;}'
+assert 8 'int main(){return 1 << 2 + 1;}'
+assert 1 'int main(){return 1 < 1 << 2;}'
+assert 1 'int main(){return 1 | 0 && 2 | 0;}'
+assert 2 'int main(){int a;a=6;a&=3;return a;}'
+assert 7 'int main(){int a;a=6;a|=3;return a;}'
+assert 5 'int main(){int a;a=6;a^=3;return a;}'
+assert 24 'int main(){int a;a=6;a<<=2;return a;}'
+assert 3 'int main(){int a;a=6;a>>=1;return a;}'
+assert 4 'int main(){int a;int b;a=6;b=a>>=1;return b+1;}'
+assert 6 'int main(){int a[3];int i;a[1]=3;i=0;a[i=i+1]<<=1;return a[1]+i-1;}'
+
+assert 2 'int main(){return 1 ? 2 : 3;}'
+assert 3 'int main(){return 0 ? 2 : 3;}'
+assert 4 'int main(){return 0 ? 2 : 0 ? 3 : 4;}'
+assert 3 'int main(){return 1 ? 0 ? 2 : 3 : 4;}'
+assert 5 'int main(){int a;a=1;a = a > 0 ? 5 : 6;return a;}'
+assert 3 'int main(){int a;int b;a=0;b=0;1 ? (a=3) : (b=4);return a+b;}'
+assert 8 'int main(){int *p;return sizeof(1 ? p : 0);}'
+assert 8 'int main(){int *p;return sizeof(0 ? 0 : p);}'
+assert 4 'int main(){return sizeof(1 ? 1 : 2);}'
+assert 8 'int main(){int a[3];return sizeof(1 ? a : 0);}'
+assert 4 $'#include <stddef.h>\nint main(){int x; int *q = &x; return sizeof(*(0 ? NULL : q));}'
+assert 4 $'#include <stddef.h>\nint main(){int x; int *q = &x; return sizeof(*(1 ? q : NULL));}'
+assert 4 'int main(){int x; int *q = &x; return sizeof(*(1 ? (void *)0 : q));}'
+assert 1 'int main(){int x; void *v = &x; return sizeof(*(1 ? v : (void *)1));}'
+assert 2 'int main(){int a[3];int *p;a[1]=2;p=0;return *((p ? p : a) + 1);}'
+assert 40 'struct S {int a; int b;}; int main(){struct S x = {1, 2}; struct S y = {3, 4}; struct S z; int c = 0; z = c ? x : y; return z.a * 10 + (1 ? x : y).b + sizeof(c ? x : y);}'
+assert 2 'int g; void f(){g = 1;} void h(){g = 2;} int main(){int c = 0; c ? f() : h(); return g;}'
+assert 10 'int dbl(int x){return x * 2;} int inc(int x){return x + 1;} int main(){int c = 1; return (c ? dbl : inc)(5);}'
+assert_error 'struct S {int a;}; struct T {int a;}; int main(){struct S s; struct T t; return (1 ? s : t).a;}'
+assert_error 'struct S {int a;}; int main(){struct S s; return (1 ? s : 1).a;}'
+assert_error 'void f(){} int main(){return 1 ? f() : 2;}'
+
+assert 3 'int main(){return (1, 2, 3);}'
+assert 5 'int main(){int a;int b;a = (b = 2, b + 3);return a;}'
+assert 5 'int main(){return sum((1, 2), 3);}'
+assert 4 'int main(){int *p;int a;return sizeof((a, p)) / 2;}'
+
 assert 3 'int main(){int a;a=3;return 3;}'
 assert 8 'int main(){int a;a=3;int b;b=5;return a+b;}'
 assert 8 'int main(){int foo;foo=3;int bar;bar=5;return foo+bar;}'
@@ -67,6 +247,64 @@ assert 15 'int main(){int sum;int i;sum=0;for(i=0;i<=5;i=i+1)sum=sum+i; return s
 assert 10 'int main(){int sum;int i;sum=0;for(i=0;i<5;i=i+1)sum=sum+i; return sum;}'
 assert 10 'int main(){int sum;sum=0;int i; i=0;for(;i<5;i=i+1)sum=sum+i; return sum;}'
 assert 5 'int main(){int i;i=0;for(;i<5;)i=i+1; return i;}'
+assert 50 'int main(){int i;int j;int s;s=0;for(i=0, j=10; i<j; i++, j--){s=s+i+j;} return s;}'
+assert 5 'int main(){int i, j;i=2;j=3;return i+j;}'
+assert 3 'int main(){int a, *p, b;p=&b;*p=3;a=0;return a+b;}'
+assert 6 'int main(){int a[2], b;a[0]=1;a[1]=2;b=3;return a[0]+a[1]+b;}'
+assert 16 'int main(){int a, *p;return sizeof(a)+sizeof(p)+sizeof(int);}'
+assert 3 'int main(){int i;i=0;while(1){if(i==3)break;i++;}return i;}'
+assert 4 'int main(){int i;for(i=0;;i++){if(i==4)break;}return i;}'
+assert 25 'int main(){int i;int s;s=0;for(i=0;i<10;i++){if(i%2==0)continue;s+=i;}return s;}'
+assert 30 'int main(){int i;int s;i=0;s=0;while(i<10){i++;if(i%2)continue;s+=i;}return s;}'
+assert 12 'int main(){int i;int j;int s;s=0;for(i=0;i<4;i++){for(j=0;j<10;j++){if(j==3)break;s++;}if(i==10)break;}return s;}'
+assert 8 'int main(){int i;int j;int s;s=0;for(i=0;i<4;i++){j=0;while(1){j++;if(j==1)continue;if(j>3)break;s++;}}return s;}'
+assert 10 'int main(){int i;i=0;do i++; while(i<10);return i;}'
+assert 1 'int main(){int i;i=0;do{i++;}while(0);return i;}'
+assert 5 'int main(){int i;i=0;do{i++;if(i==5)break;}while(1);return i;}'
+assert 3 'int main(){int i;int s;i=0;s=0;do{i++;if(i>3)continue;s++;}while(i<10);return s;}'
+assert 10 'int main(){int i;i=0;do{i++;continue;}while(i<10);return i;}'
+assert 5 'int main(){int i;i=0;switch(2){case 1:i=3;break;case 2:i=5;break;case 3:i=7;break;}return i;}'
+assert 7 'int main(){int i;i=0;switch(3){case 1:i=3;break;case 2:i=5;break;case 3:i=7;break;}return i;}'
+assert 9 'int main(){int i;i=0;switch(4){case 1:i=3;break;case 2:i=5;break;default:i=9;}return i;}'
+assert 0 'int main(){int i;i=0;switch(4){case 1:i=3;break;case 2:i=5;break;}return i;}'
+assert 12 'int main(){int i;i=0;switch(1){case 1:i+=3;case 2:i+=4;case 3:i+=5;}return i;}'
+assert 6 'int main(){int i;i=0;switch(-1){case -1:i=6;break;case 1+1:i=5;}return i;}'
+assert 5 'int main(){int i;i=0;switch(2){case 1:i=3;break;case 1+1:i=5;}return i;}'
+assert 42 'int f(int x){switch(x){case 0:return 10;case 1:return 11;case 2:return 12;case 3:return 13;case 5:return 15;default:return 42;}} int main(){return f(4);}'
+assert 15 'int f(int x){switch(x){case 0:return 10;case 1:return 11;case 2:return 12;case 3:return 13;case 5:return 15;default:return 42;}} int main(){return f(5);}'
+assert 10 'int f(int x){switch(x){case 0:return 10;case 1:return 11;case 2:return 12;case 3:return 13;case 5:return 15;default:return 42;}} int main(){return f(0);}'
+assert 42 'int f(int x){switch(x){case 0:return 10;case 1:return 11;case 2:return 12;case 3:return 13;case 5:return 15;default:return 42;}} int main(){return f(-1);}'
+assert 42 'int f(int x){switch(x){case 0:return 10;case 1:return 11;case 2:return 12;case 3:return 13;case 5:return 15;default:return 42;}} int main(){return f(6);}'
+assert 23 'int f(int x){switch(x){case 10:return 20;case 11:return 21;case 12:return 22;case 13:return 23;}return 0;} int main(){return f(13);}'
+assert 0 'int f(int x){switch(x){case 10:return 20;case 11:return 21;case 12:return 22;case 13:return 23;}return 0;} int main(){return f(9);}'
+assert 10 'int main(){int i;int s;s=0;for(i=0;i<5;i++){switch(i){case 1:continue;case 3:break;default:s+=i;}s+=1;}return s;}'
+assert 3 'int main(){int i;i=0;switch(1){case 1:switch(2){case 2:i=2;break;}i++;break;}return i;}'
+assert 2 'int main(){long x = 1L << 32; switch (x) { case 0: return 1; default: return 2; }}'
+assert 3 'int main(){long x = 0x100000002; switch (x) { case 0x100000000: return 1; case 0x100000001: return 2; case 0x100000002: return 3; case 0x100000003: return 4; } return 5;}'
+assert 5 'int main(){long x = 2; switch (x) { case 0x100000000: return 1; case 0x100000001: return 2; case 0x100000002: return 3; case 0x100000003: return 4; } return 5;}'
+assert 2 'int main(){long x = 9223372036854775807; switch (x) { case -9223372036854775807 - 1: return 1; case 9223372036854775807: return 2; default: return 3; }}'
+assert 1 'int main(){unsigned u = -1; switch (u) { case -1: return 1; default: return 2; }}'
+assert 2 'int main(){char c = -1; switch (c) { case 255: return 1; case -1: return 2; } return 3;}'
+assert_error 'int main(){switch(1){case 1:case 1:return 0;}return 1;}'
+assert_error 'int main(){switch(1){default:return 0;default:return 1;}}'
+assert_error 'int main(){int x;switch(1){case x:return 0;}return 1;}'
+assert_error 'int main(){unsigned u = 0; switch (u) { case -1: case 4294967295: return 0; } return 1;}'
+assert_error 'int main(){case 1:return 0;}'
+assert 3 'int main(){int i;i=0;goto a;a:i++;b:i++;c:i++;return i;}'
+assert 2 'int main(){int i;i=0;goto e;d:i++;e:i++;f:i++;return i;}'
+assert 1 'int main(){int i;i=0;goto j;g:i++;h:i++;j:i++;return i;}'
+assert 10 'int main(){int i;i=0;loop:if(i<10){i++;goto loop;}return i;}'
+assert 5 'int f(){goto x;x:return 5;} int main(){goto x;x:return f();}'
+assert 7 'int main(){int *p;p=&&done;goto *p;return 1;done:return 7;}'
+assert 4 'int main(){int i;int *p;i=0;p=&&inc;inc:i++;if(i<4)goto *p;return i;}'
+assert 3 'int main(){int i = 0; {goto end; i = 9; end: ;} i = 3; return i;}'
+assert 5 'int main(){int i; for (i = 0; i < 5; i++); return i;}'
+assert 2 'int f(int x){int r = 0; if (x) ; else r = 2; switch (x) { case 0: ; case 1: ; } return r;} int main(){return f(0);}'
+assert 4 'int main(){int i = 0; while (i++ < 3) ;;; return i;}'
+assert_error 'int main(){goto nowhere;return 0;}'
+assert_error 'int main(){a:return 0;a:return 1;}'
+assert_error 'int main(){break;}'
+assert_error 'int main(){if(1)continue;return 0;}'
 
 assert 5 'int main(){int sum;sum=0;while(sum < 10){sum=sum+5;return sum;} return sum;}'
 assert 10 'int main(){int sum;sum=0;while(sum < 10){sum=sum+5;} return sum;}'
@@ -100,6 +338,20 @@ assert 8 'int main(){int a;int *b;return sizeof(b+4);}'
 assert 4 'int main(){int a;int *b;return sizeof(*b);}'
 assert 4 'int main(){int a;int *b;return sizeof(1);}'
 assert 4 'int main(){int a;int *b;return sizeof(sizeof(1));}'
+assert 4 'int main(){return sizeof(int);}'
+assert 8 'int main(){return sizeof(int *);}'
+assert 8 'int main(){return sizeof(int **);}'
+assert 12 'int main(){return sizeof(int[3]);}'
+assert 12 'int main(){int a[3];return sizeof(a);}'
+assert 4 'int main(){return _Alignof(int);}'
+assert 8 'int main(){return _Alignof(int *);}'
+assert 4 'int main(){return _Alignof(int[3]);}'
+assert 8 'int main(){int *p;return _Alignof p;}'
+assert 4 'int main(){return alignof(int);}'
+
+assert 3 'int main(){int a;int *p;int b;a=1;b=2;p=&b;return a+*p;}'
+assert 7 'int main(){int a;int *p;int b[3];int *q;a=1;b[2]=6;p=&a;q=b+2;return *p+*q;}'
+assert 6 'int f(int a, int *p, int b){return a+*p+b;} int main(){int x;x=2;return f(1,&x,3);}'
 
 assert 3 'int main(){int a[3];*a = 3;return *a;}'
 assert 3 'int main(){int a[3];int *p;p = a;*p = 3;return *p;}'
@@ -118,5 +370,369 @@ assert 3 'int a; int main(){a = 3; int b; b = a; return b;}'
 assert 5 'int a; int add(int b){return a+b;} int main(){a = 3; return add(2);}'
 assert 5 'int a[3]; int add(int b){return a[0]+b;} int main(){a[0] = 3; return add(2);}'
 assert 5 'int a[3]; int add(int b){return a[1]+b;} int main(){a[1] = 3; int *p;p=a;return add(2);}'
+assert 7 'int a, *b, c[3]; int main(){a=1;b=&a;c[2]=5;return *b+c[2]+sizeof(b)/8;}'
+assert 3 'int a, b; int main(){a = 1, b = 2;return a+b;}'
+
+assert 3 'int main(){int x = 3;return x;}'
+assert 7 'int main(){int x = 3, y = x + 4;return y;}'
+assert 5 'int main(){int x = {5};return x;}'
+assert 3 'int main(){int x;int *p = &x;*p = 3;return x;}'
+assert 6 'int main(){int a[3] = {1, 2, 3};return a[0]+a[1]+a[2];}'
+assert 0 'int main(){int a[3] = {1};return a[1]+a[2];}'
+assert 1 'int main(){int a[3] = {1,};return a[0];}'
+assert 0 'int main(){int i;int a[10] = {};int s;s=0;for(i=0;i<10;i++)s+=a[i];return s;}'
+assert 4 'int main(){int a[] = {1, 2, 3, 4};return sizeof(a)/sizeof(int);}'
+assert 5 'int main(){int a[5] = {[3] = 7};return a[0]+a[1]+a[2]+a[4]+sizeof(a)/4;}'
+assert 9 'int main(){int a[5] = {1, [3] = 3, 5};return a[0]+a[3]+a[4];}'
+assert 6 'int main(){int a[] = {[5] = 6};return sizeof(a)/4 + a[0] + a[4];}'
+assert 2 'int main(){int x = 1;int *a[2] = {&x, &x};*a[1] = 2;return *a[0];}'
+assert 3 'int main(){int i;for(i=0;i<3;i++){int a[2] = {i};if(a[1])return 99;}return i;}'
+assert_error 'int main(){int a[2] = {1, 2, 3};return 0;}'
+assert_error 'int main(){int a[];return 0;}'
+assert_error 'int main(){int a[2] = {.x = 1};return 0;}'
+
+assert 3 'int x = 3; int main(){return x;}'
+assert 0 'int x = 0; int main(){return x;}'
+assert 10 'int a[4] = {1, 2, 3, 4}; int main(){return a[0]+a[1]+a[2]+a[3];}'
+assert 3 'int a[4] = {1, 2}; int main(){return a[0]+a[1]+a[2]+a[3];}'
+assert 5 'int a[] = {1, 2, 3, 4, 5}; int main(){return sizeof(a)/4;}'
+assert 4 'int a[5] = {[2] = 4}; int main(){return a[2]+a[0]+a[4];}'
+assert 12 'int x = 2 * 6; int main(){return x;}'
+assert 3 'int x = 3; int *p = &x; int main(){return *p;}'
+assert 7 'int x = 3; int *p = &x; int main(){*p = 7;return x;}'
+assert 3 'int a[3] = {1, 2, 3}; int *p = a + 2; int main(){return *p;}'
+assert 2 'int a[3] = {1, 2, 3}; int *p = &a[1]; int main(){return *p;}'
+assert 1 'int a[3] = {1, 2, 3}; int *p = a; int main(){return *p;}'
+assert 5 'int x = 5; int *p[2] = {0, &x}; int main(){return *p[1] + (p[0] != 0);}'
+assert_error 'int x = 3; int y = x; int main(){return y;}'
+assert_error 'int x; int y = &x; int main(){return y;}'
+
+assert 7 'struct S {int a; int b;}; int main(){struct S s = {3, 4}; return s.a + s.b;}'
+assert 7 'struct S {int a; int b;}; int main(){struct S s = {.b = 7}; return s.a + s.b;}'
+assert 9 'struct S {char c; long l; short h;}; int main(){struct S s = {.h = 9, .c = 1}; return s.l + s.h;}'
+assert 3 'struct S {int a; int b;}; struct S g = {1, 2}; int main(){return g.a + g.b;}'
+assert 9 'struct S {int a; int b;}; struct S g = {.b = 9}; int main(){return g.a + g.b;}'
+assert 1 'struct T {char c; long l; short h;}; struct T g = {1, 0x123456789, 7}; int main(){return g.c == 1 && g.l == 0x123456789 && g.h == 7 && sizeof(g) == 24;}'
+assert 9 'union U {char c; int i;}; int main(){union U u = {.c = 2, .i = 265}; return u.c;}'
+assert 4 'union U {char c; int i;}; union U g = {.i = 0x01020304}; int main(){return g.c;}'
+assert 1 'struct N {struct {int a; int b;} s; int arr[3]; union {char c; int i;} u;}; struct N g = {{5, 6}, {7, 8}, .u = {.c = 3}}; int main(){struct N n = {{1}, {2, 3}}; return n.s.a == 1 && n.s.b == 0 && n.arr[1] == 3 && n.arr[2] == 0 && n.u.i == 0 && g.s.b == 6 && g.arr[1] == 8 && g.arr[2] == 0 && g.u.i == 3;}'
+assert 1 'struct S {int a; int b;}; struct S g[3] = {{1, 2}, [2] = {.a = 5}}; int main(){struct S l[2] = {{1, 2}, {3, 4}}; return g[0].b == 2 && g[1].a == 0 && g[2].a == 5 && l[1].a == 3 && l[0].b == 2;}'
+assert 1 'struct A {int x; union {int y; char z;}; int w;}; struct A g = {.y = 4, 5}; int main(){struct A a = {.z = 3, .w = 8}; return a.x == 0 && a.y == 3 && a.w == 8 && g.x == 0 && g.y == 4 && g.w == 5;}'
+assert 30 'int main(){int a[2][2] = {1, 2, 3, 4}; return a[0][0] + a[0][1] * 2 + a[1][0] * 3 + a[1][1] * 4;}'
+assert 14 'struct {int a[2]; int b;} s = {1, 2, 3}; int main(){return s.a[0] + s.a[1] * 2 + s.b * 3;}'
+assert 53 'struct T {int a[2]; int b;}; int main(){struct T t[2] = {1, 2, 3, 4, 5}; return t[1].a[1] * 10 + t[1].b + t[0].b;}'
+assert 19 'int a[][2] = {1, 2, 3}; int main(){return sizeof(a) + a[1][0];}'
+assert 113 'int main(){int a[2][3] = {1, 2, [1] = 3, 4}; return a[0][0] + a[0][1] + a[0][2] + a[1][0] * 10 + a[1][1] * 20;}'
+assert 14 'struct P {int x; int y;}; struct L {struct P p; int n;}; int main(){struct P q = {4, 5}; struct L l[2] = {q, 1, 2, 3, 6}; return l[0].p.y + l[0].n + l[1].p.x + l[1].n;}'
+assert_error 'int main(){int a[2][2] = {1, 2, 3, 4, 5}; return 0;}'
+assert_error 'struct S {int a; int b;}; int main(){struct S s = {1, 2, 3}; return 0;}'
+assert_error 'struct S {int a; int b;}; int main(){struct S s = {.c = 1}; return 0;}'
+assert_error 'union U {char c; int i;}; int main(){union U u = {1, 2}; return 0;}'
+assert_error 'struct S {int a; int b;}; int main(){struct S s = {[0] = 1}; return 0;}'
+assert_error 'struct S {int a; int b;}; int x; struct S g = {x}; int main(){return 0;}'
+
+assert 1 'int main(){int x = 1;{int x = 2;} return x;}'
+assert 2 'int main(){int x = 1;{int x = 2; return x;}}'
+assert 3 'int main(){int x = 1;{int y = 2; x = x + y;} return x;}'
+assert 5 'int main(){int x = 1;{int x = 2;{int x = 5; return x;}}}'
+assert 4 'int main(){int x = 4;{int x = 2;{x = 3;}} return x;}'
+assert 10 'int main(){int s = 0;for(int i = 0; i < 5; i++) s += i; return s;}'
+assert 7 'int main(){int i = 7;for(int i = 0; i < 5; i++){} return i;}'
+assert 3 'int main(){for(int i = 0; i < 3; i++){}int i = 3; return i;}'
+assert 9 'int main(){int s = 0;for(int i = 0, j = 3; i < j; i++) s += j; return s;}'
+assert 2 'int x = 2; int main(){{int x = 5;} return x;}'
+assert_error 'int main(){int x;int x;return 0;}'
+assert_error 'int main(){{int y = 1;} return y;}'
+assert_error 'int main(){for(int i = 0; i < 3; i++){} return i;}'
+
+assert 3 'static int x = 3; int main(){return x;}'
+assert 9 'static int ext_count = 9; int main(){return ext_count;}'
+assert 5 'static int sum(int a, int b){return a * b;} int main(){return sum(2, 3) - 1;}'
+assert 3 'extern int ext_count; int main(){return ext_count;}'
+assert 6 'extern int ext_array[3]; int main(){return ext_array[0] + ext_array[1] + ext_array[2];}'
+assert 4 'extern int ext_count; int main(){ext_count = 4; return ext_count;}'
+assert 3 'int main(){extern int ext_count; return ext_count;}'
+assert 7 'int main(){int ext_count = 1;{extern int ext_count; return ext_count + 4;}}'
+assert 5 'extern int x; int x = 5; int main(){return x;}'
+assert 5 'int x = 5; extern int x; int main(){return x;}'
+assert 14 'int ext_callback(int x){return x * 2;} int main(){return call_callback(7);}'
+assert 0 'static int ext_callback(int x){return x * 2;} int main(){return call_callback(7);}'
+assert 0 'static int ext_callback(int x); int ext_callback(int x){return x * 2;} int main(){return call_callback(7);}'
+assert 0 'static int ext_callback(int x); extern int ext_callback(int x); int ext_callback(int x){return x * 2;} int main(){return call_callback(7);}'
+assert 14 'int ext_callback(int x); int ext_callback(int x){return x * 2;} int main(){return call_callback(7);}'
+assert 8 'int twice(int x); int main(){return twice(4);} int twice(int x){return x * 2;}'
+assert 6 'int count(){static int n; n++; return n;} int main(){count(); count(); return count() * 2;}'
+assert 12 'int count(){static int n = 10; return n++;} int main(){count(); return count() + 1;}'
+assert 3 'int f(){static int n = 1; return n;} int g(){static int n = 2; return n;} int main(){return f() + g();}'
+assert 5 'int main(){static int a[3] = {1, 2}; static int *p = &a[1]; return a[0] + *p + a[2] + 2;}'
+assert 2 'int main(){static int x = 2;{static int x = 5;} return x;}'
+assert_error 'extern int x = 3; int main(){return x;}'
+assert_error 'int x = 1; int x = 2; int main(){return x;}'
+assert_error 'int main(){int y = 1; static int x = y; return x;}'
+assert_error 'int main(){int x; static int x; return x;}'
+
+assert 3 'int main(){const int x = 3; return x;}'
+assert 3 'int main(){int const x = 3; return x;}'
+assert 4 'int main(){const int a[2] = {1, 3}; return a[0] + a[1];}'
+assert 5 'int main(){int x = 1; const int *p = &x; x = 5; return *p;}'
+assert 6 'int main(){int x = 1; int y = 6; const int *p = &x; p = &y; return *p;}'
+assert 7 'int main(){int x = 1; int *const p = &x; *p = 7; return x;}'
+assert 2 'int main(){int x = 2; const int *p = &x; const int *q = p; return *q;}'
+assert 3 'int main(){int x = 3; int *p = &x; const int *q = p; return *q;}'
+assert 8 'int main(){volatile int x = 8; return x;}'
+assert_asm_count 3 'DWORD PTR \[rax\]' 'int main(){volatile int x = 1; return x + x + x;}'
+assert_asm_count 3 'mov \[rax\], edi' 'int main(){volatile int x; x = 1; x = 2; x = 3; return 0;}'
+assert_asm_count 2 'DWORD PTR \[rax\]' 'int main(){volatile int x; volatile int *p = &x; *p = 1; *p = 2; return *p + *p;}'
+assert 9 'int main(){int x = 9; int *restrict p = &x; return *p;}'
+assert 4 'int main(){return sizeof(const int);}'
+assert 8 'int main(){return sizeof(int *const);}'
+assert 1 'int f(const int *p){return *p;} int main(){int x = 1; return f(&x);}'
+assert 1 'int main(){int *p = 0L; return p == 0;}'
+assert 0 'int main(){char c = 0; int *p; p = c; return p != 0;}'
+assert 1 'int main(){_Bool b = 0; unsigned u = 0; int *p = b; int *q = u; return p == q;}'
+assert 2 'int f(const int *p){return *p;} int *g(int *p){return p;} int main(){int x = 2; return f(g(&x));}'
+assert 3 'static const int x = 3; int main(){return x;}'
+assert 5 'const static int x = 5; int main(){return x;}'
+assert 2 'int main(){for(const int i = 2;;) return i;}'
+assert_error 'int main(){const int x = 3; x = 4; return x;}'
+assert_error 'int main(){const int x = 3; x += 4; return x;}'
+assert_error 'int main(){const int x = 3; x++; return x;}'
+assert_error 'int main(){const int x = 3; --x; return x;}'
+assert_error 'int main(){const int a[2] = {1, 2}; a[0] = 3; return a[0];}'
+assert_error 'int main(){int x; const int *p = &x; *p = 1; return x;}'
+assert_error 'int main(){int x; int *const p = &x; p = 0; return x;}'
+assert_error 'int f(const int x){x = 1; return x;} int main(){return f(0);}'
+assert_error 'int main(){const int x = 3; int *p = &x; return *p;}'
+assert_error 'int main(){int x; const int *p = &x; int *q; q = p; return 0;}'
+assert_error 'const int x = 3; int *p = &x; int main(){return 0;}'
+assert_error 'int main(){const int a[2] = {1, 2}; int *p = a; return *p;}'
+assert_error 'int f(int *p){return *p;} int main(){const int x = 1; return f(&x);}'
+assert_error 'int g(int a, int *p){return *p;} int main(){const int x = 1; return g(0, &x);}'
+assert_error 'int *f(const int *p){return p;} int main(){return 0;}'
+assert_error 'int main(){restrict int x; return 0;}'
+assert_error 'int main(){static extern int x; return 0;}'
+assert_error 'int main(){int int x; return 0;}'
+assert_error 'int f(static int x){return x;} int main(){return 0;}'
+
+assert 0 'int x; int main(){return x;}'
+assert 0 'int x; int x; int main(){return x;}'
+assert 3 'int x; int x = 3; int x; int main(){return x;}'
+assert 4 'int x = 4; int x; int main(){return x;}'
+assert 5 'int x; int main(){x = 5; return x;}'
+assert 0 'int a[100]; int main(){return a[0] + a[99];}'
+assert 9 'int a[100]; int main(){a[99] = 9; return a[0] + a[99];}'
+assert 0 'int x = 0; int y = 0; int main(){return x + y;}'
+assert 6 'static int x; int main(){x = 6; return x;}'
+assert 7 'int ext_common; int main(){return ext_common;}'
+assert 3 'const int a[2] = {1, 2}; int main(){return a[0] + a[1];}'
+assert 6 'int x = 6; const int *const p = &x; int main(){return *p;}'
+assert 12 'int x; int *p = &x; int y = 4; int main(){*p = 8; return x + y;}'
+assert 4 'int x; int main(){int *p = &x; *p = 4; return x;}'
+assert 139 'const int t[64] = {0}; int main(){int *p = (int *)t; *p = 1; return 0;}'
+assert 139 'char *const cp = 0; int main(){char **p = (char **)&cp; *p = 0; return 0;}'
+assert 139 'int main(){char *s = "abc"; s[0] = 120; return 0;}'
+assert_error 'int x = 1; int x; int x = 2; int main(){return x;}'
+
+assert 3 $'int main(){\n  // a comment\n  return /* another */ 3;\n}'
+assert 4 $'int main(){\n  return 1 + \\\n    3;\n}'
+assert 6 $'#include "tests/include/twice.h"\nint main(){return twice(3);}'
+assert 6 $'#include "tests/include/twice.h"\n#include "tests/include/twice.h"\nint main(){return twice(3);}'
+assert 8 $'#include "twice.h"\nint main(){return twice(4);}' -Itests/include
+assert 8 $'#include <twice.h>\nint main(){return twice(4);}' -I tests/include
+assert 5 $'#include <guarded.h>\n#include <guarded.h>\nint main(){return guarded;}' -Itests/include
+assert 3 $'#include <outer.h>\n#include <sub/inner.h>\nint main(){return outer();}' -Itests/include
+assert 2 $'  #  include <twice.h>\n#\nint main(){return twice(1);}' -Itests/include
+assert 1 $'#pragma pack\nint main(){return 1;}'
+assert_error $'#include "twice.h"\nint main(){return twice(4);}'
+assert_error $'#include <missing.h>\nint main(){return 0;}' -Itests/include
+assert_error $'#include <twice.h> x\nint main(){return 0;}' -Itests/include
+assert_error $'#include <self.h>\nint main(){return 0;}' -Itests/include
+assert_error $'#include\nint main(){return 0;}'
+assert_error $'#bogus\nint main(){return 0;}'
+assert_error $'int main(){return 0;} /* unclosed'
+
+assert 3 $'#define N 3\nint main(){return N;}'
+assert 9 $'#define SQ(x) ((x) * (x))\nint main(){return SQ(1 + 2);}'
+assert 4 $'#define TWO 1 + 1\n#define FOUR TWO * 2\nint main(){return FOUR + 1;}'
+assert 4 $'#define foo foo\nint foo = 4;\nint main(){return foo;}'
+assert 6 $'int f(int x){return x;}\n#define f(x) x * g\n#define g f\nint main(){return f(2)(3);}'
+assert 5 $'#define CAT(a, b) a ## b\nint xy = 5;\nint main(){return CAT(x, y);}'
+assert 12 $'#define CAT(a, b) a ## b\nint main(){return CAT(1, 2);}'
+assert 3 $'#define CAT(a, b) a ## b\nint main(){return CAT(, 3) CAT(,);}'
+assert 5 $'#define SUM(...) sum(__VA_ARGS__)\nint main(){return SUM(2, 3);}'
+assert 7 $'#define CALL(f, ...) f(__VA_ARGS__)\nint main(){return CALL(sum, 3, 4);}'
+assert 1 $'#define F(a, ...) a __VA_OPT__(+ sum(__VA_ARGS__))\nint main(){return F(1);}'
+assert 6 $'#define F(a, ...) a __VA_OPT__(+ sum(__VA_ARGS__))\nint main(){return F(1, 2, 3);}'
+assert 7 $'#define C(f, a, args...) f(a , ## args)\nint one(int x){return x;}\nint main(){return C(one, 7);}'
+assert 7 $'#define C(f, a, args...) f(a , ## args)\nint main(){return C(sum, 3, 4);}'
+assert 3 $'#define ZERO() 3\nint main(){return ZERO();}'
+assert 2 $'#define ID(x) x\nint ID = 2;\nint main(){return ID;}'
+assert 8 $'#define ID(x) x\nint main(){return ID((1, 8));}'
+assert 5 $'#define N 3\n#undef N\nint N = 5;\nint main(){return N;}'
+assert 4 $'#define N 3\n#define N 4\nint main(){return N;}'
+assert 6 $'#define LONG 1 + \\\n  2 + 3\nint main(){return LONG;}'
+assert 7 'int main(){return N;}' -DN=7
+assert 1 'int main(){return N;}' -D N
+assert 9 'int N = 9; int main(){return N;}' -DN=3 -UN
+assert 6 'int main(){return SQ(2) + 2;}' '-DSQ(x)=x*x'
+assert 8 $'#define H <twice.h>\n#include H\nint main(){return twice(4);}' -Itests/include
+assert 2 $'#include <guarded.h>\nint main(){return guarded - 3;}' -Itests/include
+assert 2 $'#include <guarded.h>\nint guarded = 2;\nint main(){return guarded;}' -Itests/include -DGUARDED_H
+assert_error $'#include <guarded.h>\n#undef GUARDED_H\n#include <guarded.h>\nint main(){return 0;}' -Itests/include
+assert_error $'#define SQ(x) x * x\nint main(){return SQ(1, 2);}'
+assert_error $'#define ADD(x, y) x + y\nint main(){return ADD(1);}'
+assert_error $'#define SQ(x) x * x\nint main(){return SQ(1;}'
+assert_error $'#define 1 2\nint main(){return 0;}'
+assert_error $'#define\nint main(){return 0;}'
+assert_error $'#define F(x) ## x\nint main(){return 0;}'
+assert_error $'#define F(x) # y\nint main(){return 0;}'
+assert_error $'#define F(x, x) x\nint main(){return 0;}'
+assert_error $'#define CAT(a, b) a ## b\nint main(){return CAT(+, /);}'
+assert_error $'#undef\nint main(){return 0;}'
+
+assert 1 $'#if 1 + 2 * 3 == 7\nint main(){return 1;}\n#else\nint main(){return 2;}\n#endif'
+assert 2 $'#if 0\nint main(){return 1;}\n#else\nint main(){return 2;}\n#endif'
+assert 3 $'#if 0\n#elif 0\n#elif 2 > 1\nint main(){return 3;}\n#else\n#endif'
+assert 4 $'#define N 4\n#if defined N && defined(N) && !defined M\nint main(){return N;}\n#endif'
+assert 5 $'#if UNDEFINED\nint main(){return 1;}\n#else\nint main(){return 5;}\n#endif'
+assert 6 $'#if 1 ? 0 : 1\n#else\n# if (2 << 1) - 4 || 3 % 2\nint main(){return 6;}\n# endif\n#endif'
+assert 7 $'#if 0\n# if 1\n#  error nested\n# else\n# endif\n#elif 1\nint main(){return 7;}\n#endif'
+assert 8 $'#ifdef N\nint main(){return N;}\n#else\nint main(){return 0;}\n#endif' -DN=8
+assert 9 $'#ifndef N\nint main(){return 9;}\n#endif'
+assert 1 $'#define N 1\n#ifdef N\n#undef N\n#endif\n#ifndef N\nint main(){return 1;}\n#endif'
+assert 2 $'#if 1\nint main(){return 2;}\n#elif 1 / 0\n#endif'
+assert 3 $'#if 2147483647 + 1 < 0 || -1 > 0xffffffff\nint main(){return 0;}\n#else\nint main(){return 3;}\n#endif'
+assert 0 $'#warning not fatal\nint main(){return 0;}'
+assert 3 $'#line 100 "other.c"\nint main(){return 3;}'
+assert_error $'#error stop here\nint main(){return 0;}'
+assert_error $'#if 1\nint main(){return 0;}'
+assert_error $'#endif\nint main(){return 0;}'
+assert_error $'#else\nint main(){return 0;}'
+assert_error $'#if 1\n#else\n#elif 1\n#endif\nint main(){return 0;}'
+assert_error $'#if\n#endif\nint main(){return 0;}'
+assert_error $'#if 1 2\n#endif\nint main(){return 0;}'
+assert_error $'#ifdef\n#endif\nint main(){return 0;}'
+
+assert 3 $'\n\nint main(){return __LINE__;}'
+assert 4 $'#define L __LINE__\n\n\nint main(){return L;}'
+assert 3 $'int main(){return __COUNTER__ + __COUNTER__ + __COUNTER__;}'
+assert 1 $'#if __STDC__ && __STDC_VERSION__ >= 201112 && defined __x86_64__ && defined __linux__\nint main(){return 1;}\n#endif'
+assert 11 $'#line 10\n\nint main(){return __LINE__;}'
+assert 20 $'#line \\\n 20\nint main(){return __LINE__;}'
+assert 0 $'#ifdef __DATE__\n#ifdef __TIME__\n#ifdef __FILE__\nint main(){return 0;}\n#endif\n#endif\n#endif'
+assert 5 'int main(){return __STDC__ + 4;}' -U__STDC__ -D__STDC__=1
+assert_output $'# 2 "<command line>"\nint x = 1 + 2;' $'#define ADD(a, b) a + b\nint x = ADD(1, 2);' -E
+assert_output $'# 1 "<command line>"\nint x;\n\n\n\nint y;' $'int x;\n#if 0\nint z;\n#endif\nint y;' -E
+assert_output $'# 1 "<command line>"\nint x;\n# 20 "<command line>"\nint y;' $'int x;\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\n\nint y;' -E
+assert_output $'# 4 "tests/include/twice.h"\nint twice(int x) { return x * 2; }\n# 2 "<command line>"\nint y = 2;' $'#include <twice.h>\nint y = __LINE__;' -E -Itests/include
+assert_output $'# 1 "<command line>"\n"<command line>"\n# 7 "other.c"\n"other.c" 7' $'__FILE__\n#line 7 "other.c"\n__FILE__ __LINE__' -E
+SOURCE_DATE_EPOCH=123456 assert_output $'# 1 "<command line>"\n"Jan  2 1970" "10:17:36"' '__DATE__ __TIME__' -E
+
+assert 3 $'#include <stdio.h>\nint main(){return printf("%d\\n", 42);}'
+assert 5 $'#include <string.h>\nint main(){char buf[8] = "hello"; return strlen(buf);}'
+assert 8 $'#include <stddef.h>\nstruct s { char c; long l; };\nint main(){return offsetof(struct s, l);}'
+assert 7 'typedef int T; typedef T *P; int main(){T x = 7; P p = &x; return *p;}'
+assert 3 'typedef int T; int main(){{ int T = 3; return T; }}'
+assert 24 'struct s { char c; int i; long l; short h; }; int main(){return sizeof(struct s);}'
+assert 7 'struct s { int a; struct { int b; } in; }; int main(){struct s x; struct s *p = &x; p->in.b = 7; return x.in.b;}'
+assert 4 'union u { int i; char c; }; int main(){union u x; x.i = 0; x.c = 4; return x.i + sizeof(union u) - 4;}'
+assert 2 'struct s { union { int a; int b; }; }; int main(){struct s x; x.a = 2; return x.b;}'
+assert 3 'struct s { int a; }; int main(){struct s x; x.a = 3; struct s y = x; return y.a;}'
+assert 7 'struct n { int v; struct n *next; }; int main(){struct n a; struct n b; b.v = 7; a.next = &b; return a.next->v;}'
+assert 19 'typedef struct node node; struct node { int v; node *next; }; int main(){node a; a.v = 3; a.next = &a; return a.next->next->v + sizeof(node);}'
+assert 13 'struct s; struct s *p; struct s { int a; int b; }; int main(){struct s x; x.b = 5; p = &x; return p[0].b + sizeof(struct s);}'
+assert 4 'struct s { int a; }; int main(){struct s; struct s { char c; }; return sizeof(struct s) + 3;}'
+assert_error 'int main(){struct S; return sizeof(struct S);}'
+assert_error 'int main(){struct S; return _Alignof(struct S);}'
+assert_error 'struct S *p; int main(){return sizeof(*p);}'
+assert_error 'extern int a[]; int main(){return sizeof(a);}'
+assert 14 'int main(){return sizeof(char) + sizeof(short) + sizeof(long) + sizeof(unsigned int) - sizeof(_Bool);}'
+assert 44 'int main(){return (char)300;}'
+assert 255 'int main(){unsigned char c = 255; return c;}'
+assert 1 'int main(){_Bool b = 2; short s = -1; return b + (s == -1) - 1;}'
+assert 1 'int main(){char c = 127; int r = (c += 1); return r == -128 && c == -128;}'
+assert 1 'int main(){char c = 127; int r = ++c; return r == -128;}'
+assert 1 'int main(){unsigned char c = 0; int r = --c; short h = 32767; int s = (h *= 2); return r == 255 && s == -2;}'
+assert 1 'int main(){_Bool b = 0; int r = (b += 2); return r == 1 && b == 1;}'
+assert 8 'int (*fp)(int, int); int main(){return sizeof(fp) + sizeof(int (*)[3]) - 8;}'
+assert 5 'int add(int, int) __asm__("sum"); int main(){return add(2, 3);}'
+assert 10 'int dbl(int x){return x * 2;} int (*g)(int); int main(){int (*fp)(int); fp = dbl; g = fp; return fp(4) + g(1);}'
+assert 3 'int g; void set(int x){if (x < 0) return; g = x; return;} int main(){set(3); set(-1); return g;}'
+assert_error 'int f(){return;} int main(){return f();}'
+assert 8 'int dbl(int x){return x * 2;} int main(){int (*fp)(int) = dbl; return (*fp)(3) + (*dbl)(1);}'
+assert 7 'int inc(int x){return x + 1;} struct S { int (*fn)(int); }; int main(){struct S s; s.fn = inc; struct S *p = &s; return s.fn(4) + p->fn(1);}'
+assert 21 'int one(){return 1;} int two(){return 2;} int main(){int (*arr[2])() = {one, two}; int i = 1; return arr[i]() * 10 + arr[0]();}'
+assert 10 'int dbl(int x){return x * 2;} int (*pick())(int){return dbl;} int main(){return pick()(5);}'
+assert_error 'int main(){int x = 1; return x(1);}'
+assert 7 'int f(int a, int b, int c, int d, int e, int f, int g){return g;} int main(){return f(1, 2, 3, 4, 5, 6, 7);}'
+assert 1 'long f(long a, long b, long c, long d, long e, long f, char g, short h){return a + b + c + d + e + f + g * 10 + h * 100;} int main(){return f(1, 1, 1, 1, 1, 1, 2, 3) == 326;}'
+assert 7 'int sprintf(char *buf, const char *fmt, ...); int main(){char buf[16]; sprintf(buf, "%d%d%d%d%d%d%d", 1, 2, 3, 4, 5, 6, 7); return buf[6] - 48;}'
+assert 8 'int sprintf(char *buf, const char *fmt, ...); int main(){char buf[16]; sprintf(buf, "%d%d%d%d%d%d%d%d", 1, 2, 3, 4, 5, 6, 7, 8); return buf[7] - 48 + buf[8];}'
+assert 3 '__extension__ typedef long long ll; static __inline int f(int *__restrict p) __attribute__((nonnull)); int main(){ll x = 3; return x;}'
+assert_error 'int main(){float f; return 0;}'
+assert_error 'struct s; int main(){struct s x; return 0;}'
+assert_error 'signed unsigned x; int main(){return 0;}'
+assert_error 'restrict int x; int main(){return 0;}'
+assert_error 'struct s { int a : 3; }; int main(){return 0;}'
+assert_error 'struct s { int a; }; int main(){struct s x; return x.b;}'
+assert 1 'struct S { int a; int b[2]; }; int main(){const struct S s = {1}; struct S t = s; t.a = 2; return s.a;}'
+assert_error 'struct S { int a; }; int main(){const struct S s = {1}; s.a = 2; return s.a;}'
+assert_error 'struct S { int a; }; int main(){struct S t; const struct S *p = &t; p->a = 2; return 0;}'
+assert_error 'struct S { int a[2]; }; int main(){const struct S s = {{1}}; s.a[0] = 2; return 0;}'
+assert_error 'struct S { int a; }; int main(){const struct S s = {1}; s.a++; return 0;}'
+assert_error 'struct S { int a; }; int main(){const struct S s = {1}; int *p = &s.a; return *p;}'
+assert_error 'int main(){struct {const int x;} a = {1}, b = {2}; a = b; return 0;}'
+assert_error 'struct In { const char c[2]; }; struct Out { int n; struct In in; }; int main(){struct Out a; struct Out b; a = b; return 0;}'
+assert_error 'union U { int i; const long l; }; int main(){union U a; union U b; a = b; return 0;}'
+assert 2 'struct S { const int x; int y; }; int main(){struct S a = {1, 2}; struct S *p = &a; p->y = 2; return a.y;}'
+assert_error 'int main(){int x; return x.a;}'
+assert_error 'int x __asm__("y"); int main(){return 0;}'
+
+assert 1 $'#include <stdlib.h>\nint main(){return abs(-3) == 3 && strtol("0x1f", 0, 16) == 31;}'
+assert 1 'int main(){return 0x1f == 31 && 0XfF == 255 && 017 == 15 && 0 == 00 && 0b101 == 5 && 0B11 == 3;}'
+assert 1 'int main(){return sizeof(1) == 4 && sizeof(1u) == 4 && sizeof(1L) == 8 && sizeof(1ul) == 8 && sizeof(1LL) == 8 && sizeof(1ULL) == 8 && sizeof(1llu) == 8;}'
+assert 1 'int main(){return sizeof(2147483647) == 4 && sizeof(2147483648) == 8 && sizeof(0x7fffffff) == 4 && sizeof(0xffffffff) == 4 && sizeof(0x100000000) == 8;}'
+assert 1 'int main(){return -1 > 0u && 0xffffffff == -1u && (0x80000000 >> 31) == 1 && 0x7fffffff + 1u == 0x80000000;}'
+assert 18 'long g = 0x123456789a; int main(){return g >> 32;}'
+assert 1 'int main(){long x = 9223372036854775807; return x == 0x7fffffffffffffff && x - 1 > 0x100000000 && (int)0x100000001 == 1;}'
+assert 1 'int main(){unsigned x = 0; x = x - 1; return x == 4294967295 && x / 2 == 0x7fffffff && (x >> 31) == 1 && (-8 >> 1) == -4;}'
+assert 255 'unsigned char c = 0x1ff; int main(){return c;}'
+assert 1 $'#if __STDC_VERSION__ == 201112L && 0xffffffffffffffff == -1 && !(-1 < 0u)\nint main(){return 1;}\n#endif'
+assert_error 'int main(){return 0x;}'
+assert_error 'int main(){return 09;}'
+assert_error 'int main(){return 1lul;}'
+
+assert 0 $'#if 0\n1.5 09 0x 1lul\n#endif\nint main(){return 0;}'
+assert 3 $'#if 0\nit\'s broken\n#endif\nint main(){return 3;}'
+assert_error $'#if 1\nit\'s broken\n#endif\nint main(){return 3;}'
+assert_error $'int main(){char *s="ab;\nreturn 0;}'
+assert 11 $'#line 010\n\nint main(){return __LINE__;}'
+assert 1 'int main(){return 9223372036854775807 > 0 && 0xffffffffffffffff == -1 && 18446744073709551615u == -1;}'
+assert_error 'int main(){return 0b;}'
+assert_error 'int main(){return 0b102;}'
+assert_error 'int main(){return 019;}'
+assert_error 'int main(){return 1uu;}'
+assert_error 'int main(){return 1lL;}'
+assert_error 'int main(){return 1.5;}'
+assert_error 'int main(){return 0x1p3;}'
+assert_error 'int main(){return 9223372036854775808;}'
+assert_error 'int main(){return 18446744073709551616;}'
+assert_error 'int main(){return 0x10000000000000000;}'
+assert_error $'#if 09\n#endif\nint main(){return 0;}'
+assert_error $'#line 0x10\nint main(){return 0;}'
+assert_error $'#line 99999999999999999999\nint main(){return 0;}'
+
+assert_error_count '1 error generated.' 'int main(){return 0 1;}'
+assert_error_at '<command line>:1: int main(){int a; return a + bb;}' 'int main(){int a; return a + bb;}'
+assert_error_at '<command line>:2: return x[0];}' $'int main(){\nreturn x[0];}'
+assert_error_count '3 errors generated.' 'int main(){int x = 1 x = 2; return y; x = ; return x;}'
+assert_error_count '2 errors generated.' 'int f(){return a;} int g(int){return 0;} int main(){return f();}'
+assert_error_count '3 errors generated.' 'int main(){switch(1){case 1: return 1; case 1: return 2; default: return 3; default: return 4;} l: l: return 1;}'
+assert_error_count '2 errors generated.' 'int main(){if (1) {int a = ;} while (1) {break; continue 1;} int a; return a;}'
+assert_error_count '1 error generated.' 'int main(){int a[2] = {1, +}; int b = 2; return b;}'
+assert_error_count '1 error generated.' 'int g[2] = {1, +}; int main(){return 0;}'
+assert_error_count '2 errors generated.' 'int main(){return 0 1; return 0 2; return 0 3;}' -ferror-limit=2
+assert 3 'int f(){return 1;} int main(){return f() + 2;}' -ferror-limit=0
+assert_error 'int main(){return 0;}' -ferror-limit=x
 
 echo OK